@@ -0,0 +1,275 @@
+use hound::WavReader;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Properties of a WAV file, read from its header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// Number of samples per channel.
+    pub frames: u32,
+}
+
+impl AudioInfo {
+    /// Length in seconds.
+    pub fn duration(&self) -> f64 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+        self.frames as f64 / self.sample_rate as f64
+    }
+}
+
+/// Reads the header of the WAV file at `path` without decoding any audio.
+pub fn read_audio_info(path: &Path) -> Result<AudioInfo, String> {
+    let reader = WavReader::open(path)
+        .map_err(|e| format!("Failed to read WAV header of {:?}: {}", path, e))?;
+    let spec = reader.spec();
+    Ok(AudioInfo {
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+        bits_per_sample: spec.bits_per_sample,
+        frames: reader.duration(),
+    })
+}
+
+/// Decodes at most the first `max_seconds` of the WAV file at `path`, mixed
+/// down to mono and scaled to -1.0..=1.0.
+pub fn read_mono(path: &Path, max_seconds: f64) -> Result<(AudioInfo, Vec<f32>), String> {
+    let mut reader = WavReader::open(path)
+        .map_err(|e| format!("Failed to read WAV header of {:?}: {}", path, e))?;
+    let spec = reader.spec();
+    let info = AudioInfo {
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+        bits_per_sample: spec.bits_per_sample,
+        frames: reader.duration(),
+    };
+    let channels = spec.channels.max(1) as usize;
+    let max_frames = (max_seconds * spec.sample_rate as f64) as u32;
+    let limit = max_frames.min(info.frames) as usize * channels;
+    let error = |e: hound::Error| format!("Failed to decode {:?}: {}", path, e);
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .take(limit)
+            .collect::<Result<_, _>>()
+            .map_err(error)?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample.clamp(1, 32) - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .take(limit)
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()
+                .map_err(error)?
+        }
+    };
+    let mono = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((info, mono))
+}
+
+/// BLAKE3 hash, in hex, of the format and audio data of the WAV file at
+/// `path`. Metadata chunks (`LIST`, `bext`, `id3 `, ...) are left out, so
+/// the same sound tagged differently hashes the same.
+pub fn audio_checksum(path: &Path) -> Result<String, String> {
+    let error = |e: io::Error| format!("Failed to read {:?}: {}", path, e);
+    let mut file = open_wav(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let (mut fmt, mut data) = (false, false);
+    while !(fmt && data) {
+        let Some((id, size)) = next_chunk(&mut file) else {
+            return Err(format!(
+                "{:?} has no {} chunk",
+                path,
+                if fmt { "data" } else { "fmt" }
+            ));
+        };
+        if &id == b"fmt " || &id == b"data" {
+            fmt |= &id == b"fmt ";
+            data |= &id == b"data";
+            hasher.update(&id);
+            let copied = io::copy(&mut (&mut file).take(size), &mut hasher).map_err(error)?;
+            if copied < size {
+                return Err(format!("{:?} is truncated", path));
+            }
+        } else {
+            file.seek_relative(size as i64).map_err(error)?;
+        }
+        // chunks are padded to an even size
+        if size % 2 == 1 {
+            file.seek_relative(1).map_err(error)?;
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Size of the `data` chunk of the WAV file at `path`, found without reading
+/// the audio. Files with the same [`audio_checksum`] have the same data size.
+pub fn audio_data_size(path: &Path) -> Result<u64, String> {
+    let error = |e: io::Error| format!("Failed to read {:?}: {}", path, e);
+    let mut file = open_wav(path)?;
+    loop {
+        let Some((id, size)) = next_chunk(&mut file) else {
+            return Err(format!("{:?} has no data chunk", path));
+        };
+        if &id == b"data" {
+            return Ok(size);
+        }
+        file.seek_relative((size + size % 2) as i64)
+            .map_err(error)?;
+    }
+}
+
+/// Opens the WAV file at `path`, positioned at its first chunk.
+fn open_wav(path: &Path) -> Result<BufReader<File>, String> {
+    let error = |e: io::Error| format!("Failed to read {:?}: {}", path, e);
+    let mut file = BufReader::new(File::open(path).map_err(error)?);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).map_err(error)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(format!("{:?} is not a WAV file", path));
+    }
+    Ok(file)
+}
+
+/// Reads the id and size of the next chunk header, if there is one.
+fn next_chunk(file: &mut BufReader<File>) -> Option<([u8; 4], u64)> {
+    let mut chunk = [0u8; 8];
+    file.read_exact(&mut chunk).ok()?;
+    let id = [chunk[0], chunk[1], chunk[2], chunk[3]];
+    Some((
+        id,
+        u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64,
+    ))
+}
+
+/// Writes a mono 16-bit WAV file with the given samples (-1.0..=1.0) for tests.
+#[cfg(test)]
+pub fn write_test_signal(path: &Path, sample_rate: u32, samples: &[f32]) {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for sample in samples {
+        writer
+            .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// Writes a silent 16-bit WAV file for tests.
+#[cfg(test)]
+pub fn write_test_wav(path: &Path, channels: u16, sample_rate: u32, frames: u32) {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for _ in 0..frames * channels as u32 {
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_audio_info() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kick.wav");
+        write_test_wav(&path, 2, 48000, 24000);
+
+        let info = read_audio_info(&path).unwrap();
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(info.frames, 24000);
+        assert_eq!(info.duration(), 0.5);
+    }
+
+    #[test]
+    fn test_read_audio_info_not_a_wav() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("fake.wav");
+        fs::write(&path, b"not a wav file").unwrap();
+
+        let err = read_audio_info(&path).unwrap_err();
+        assert!(err.starts_with("Failed to read WAV header"));
+    }
+
+    #[test]
+    fn test_read_mono_mixes_down_and_limits() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stereo.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..10 {
+            writer.write_sample(16384i16).unwrap();
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (info, samples) = read_mono(&path, 0.0005).unwrap();
+        assert_eq!(info.frames, 10);
+        assert_eq!(samples, vec![0.25; 4]);
+    }
+
+    #[test]
+    fn test_audio_checksum_ignores_metadata() {
+        let dir = tempdir().unwrap();
+        let plain = dir.path().join("plain.wav");
+        let tagged = dir.path().join("tagged.wav");
+        let louder = dir.path().join("louder.wav");
+        write_test_signal(&plain, 8000, &[0.5, -0.5, 0.25]);
+        write_test_signal(&tagged, 8000, &[0.5, -0.5, 0.25]);
+        write_test_signal(&louder, 8000, &[0.6, -0.5, 0.25]);
+
+        // insert an odd-sized LIST chunk before the audio and fix up the RIFF size
+        let mut bytes = fs::read(&tagged).unwrap();
+        let data = bytes.windows(4).position(|w| w == b"data").unwrap();
+        bytes.splice(data..data, b"LIST\x05\0\0\0INFOa\0".iter().copied());
+        let riff_size = (bytes.len() as u32 - 8).to_le_bytes();
+        bytes[4..8].copy_from_slice(&riff_size);
+        fs::write(&tagged, &bytes).unwrap();
+
+        let checksum = audio_checksum(&plain).unwrap();
+        assert_eq!(audio_checksum(&tagged).unwrap(), checksum);
+        assert_ne!(audio_checksum(&louder).unwrap(), checksum);
+        assert_ne!(
+            crate::utils::file_checksum(&tagged).unwrap(),
+            crate::utils::file_checksum(&plain).unwrap()
+        );
+        // three 16-bit samples, past the odd-sized chunk
+        assert_eq!(audio_data_size(&tagged).unwrap(), 6);
+        assert_eq!(audio_data_size(&plain).unwrap(), 6);
+
+        fs::write(&plain, b"not a wav file").unwrap();
+        assert!(audio_checksum(&plain)
+            .unwrap_err()
+            .ends_with("is not a WAV file"));
+        assert!(audio_data_size(&plain).is_err());
+    }
+}
@@ -1,71 +1,1678 @@
-use crate::config::Config;
-use regex::Regex;
-use std::path::Path;
-
-pub fn categorize_sample(path: &Path, config: &Config) -> Option<String> {
-    let filename = path.file_name()?.to_str()?.to_lowercase();
-    for (category, patterns) in &config.patterns {
-        for pattern in patterns {
-            if let Ok(re) = Regex::new(pattern) {
-                if re.is_match(&filename) {
-                    return Some(category.clone());
+use crate::audio_info::{read_audio_info, AudioInfo};
+use crate::classifier::{classify, Classification, SoundClass};
+use crate::config::{invalid_limit, Config, InvalidSetting, Pattern, PatternKind, Rule, Target};
+use crate::template::{Template, DEFAULT_TEMPLATE};
+use crate::utils::{glob_to_regex, tokenize};
+use regex::{Regex, RegexSet};
+use std::fmt;
+use std::path::{Component, Path};
+
+/// A pattern from the config that failed to compile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternError {
+    /// Category of the rule the pattern belongs to, `None` for global excludes.
+    pub category: Option<String>,
+    /// Whether the pattern comes from `patterns`, `exclude` or `template`,
+    /// `classifier` for a setting of the classifier, or the name of another
+    /// setting that has an unusable value.
+    pub list: &'static str,
+    /// Position of the pattern inside its list.
+    pub index: usize,
+    pub pattern: String,
+    pub message: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.list {
+            "template" => format!("template {:?}", self.pattern),
+            "exclude" => format!("exclude pattern #{} {:?}", self.index, self.pattern),
+            "patterns" => format!("pattern #{} {:?}", self.index, self.pattern),
+            "classifier" => format!("classifier setting {}", self.pattern),
+            setting => format!("{} = {}", setting, self.pattern),
+        };
+        match &self.category {
+            Some(category) => write!(
+                f,
+                "invalid {} in category '{}': {}",
+                what, category, self.message
+            ),
+            None => write!(f, "invalid global {}: {}", what, self.message),
+        }
+    }
+}
+
+/// Keywords shorter than this never match fuzzily: nearly any word one
+/// letter longer than `oh` or `hat` is within a typical threshold of it.
+const MIN_FUZZY_KEYWORD_LEN: usize = 4;
+
+/// Rule set compiled once from a [`Config`] and reused for every sample.
+#[derive(Debug)]
+pub struct Categorizer {
+    /// Positive patterns of all rules; a pattern's rank is its position in
+    /// evaluation order.
+    patterns: Matcher,
+    /// Index into `rules` of each pattern, by rank.
+    owners: Vec<usize>,
+    rules: Vec<CompiledRule>,
+    exclude: Matcher,
+    /// The content classifier, if enabled.
+    fallback: Option<Fallback>,
+}
+
+/// The category a sample was sorted into, with its output settings.
+#[derive(Debug, Clone)]
+pub struct Category {
+    /// Full category path, e.g. `drums/kicks`.
+    pub name: String,
+    /// Destination path template, inherited from the closest ancestor or the
+    /// config if the rule has none.
+    pub template: Template,
+    /// Most samples per output folder before it's split, inherited like
+    /// `template`.
+    pub max_files_per_folder: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    category: Category,
+    exclude: Matcher,
+    /// Ancestors with patterns of their own; all of them must match for this
+    /// rule to be considered.
+    gates: Vec<usize>,
+    /// Audio conditions of the rule and its ancestors that are set.
+    conditions: Vec<Conditions>,
+    /// Similarity threshold for fuzzy keyword matching, if enabled.
+    fuzzy: Option<f64>,
+    /// The rule's lowercased keywords long enough for fuzzy matching.
+    keywords: Vec<(Target, String)>,
+}
+
+impl CompiledRule {
+    /// The keyword that best matches a word (or run of words) of the sample,
+    /// if its similarity reaches the rule's threshold.
+    fn fuzzy_match(&self, subject: &Subject) -> Option<FuzzyMatch> {
+        let threshold = self.fuzzy?;
+        let mut best: Option<FuzzyMatch> = None;
+        for (target, keyword) in &self.keywords {
+            let words: Vec<&str> = subject.tokens(*target).split(' ').collect();
+            let len = keyword.split(' ').count();
+            for window in words.windows(len) {
+                let word = window.join(" ");
+                let score = strsim::jaro_winkler(keyword, &word);
+                if score >= threshold && best.as_ref().is_none_or(|b| score > b.score) {
+                    best = Some(FuzzyMatch {
+                        keyword: keyword.clone(),
+                        word,
+                        score,
+                    });
                 }
             }
         }
+        best
+    }
+}
+
+/// The content classifier, consulted for samples no rule claims.
+#[derive(Debug)]
+struct Fallback {
+    min_confidence: f64,
+    /// Category of each sound class, in [`SoundClass::ALL`] order.
+    categories: Vec<Category>,
+}
+
+/// The audio conditions of one rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Conditions {
+    min_duration: Option<f64>,
+    max_duration: Option<f64>,
+    channels: Option<u16>,
+    min_sample_rate: Option<u32>,
+    max_sample_rate: Option<u32>,
+}
+
+impl Conditions {
+    /// The conditions of `rule`, or `None` if it has none.
+    fn of(rule: &Rule) -> Option<Self> {
+        let conditions = Conditions {
+            min_duration: rule.min_duration,
+            max_duration: rule.max_duration,
+            channels: rule.channels,
+            min_sample_rate: rule.min_sample_rate,
+            max_sample_rate: rule.max_sample_rate,
+        };
+        (conditions != Conditions::default()).then_some(conditions)
+    }
+
+    fn accepts(&self, info: &AudioInfo) -> bool {
+        let duration = info.duration();
+        self.min_duration.is_none_or(|min| duration >= min)
+            && self.max_duration.is_none_or(|max| duration <= max)
+            && self.channels.is_none_or(|c| info.channels == c)
+            && self
+                .min_sample_rate
+                .is_none_or(|min| info.sample_rate >= min)
+            && self
+                .max_sample_rate
+                .is_none_or(|max| info.sample_rate <= max)
+    }
+}
+
+impl Categorizer {
+    /// Compiles all patterns of `config`, collecting every invalid one instead
+    /// of stopping at the first.
+    pub fn new(config: &Config) -> Result<Self, Vec<PatternError>> {
+        let mut errors = Vec::new();
+        let mut positive = Vec::new();
+        let mut owners = Vec::new();
+        let mut rules = Vec::new();
+
+        validate(None, "exclude", &config.exclude, &mut errors);
+        let default_template = parse_template(None, config.template.as_deref(), &mut errors);
+        settings(
+            None,
+            invalid_limit(config.max_files_per_folder),
+            &mut errors,
+        );
+        if let Some(classifier) = &config.classifier {
+            for setting in classifier.invalid_settings() {
+                errors.push(PatternError {
+                    category: None,
+                    list: "classifier",
+                    index: 0,
+                    pattern: format!("{} = {}", setting.key, setting.value),
+                    message: setting.message,
+                });
+            }
+        }
+
+        // Ranks follow evaluation order, so the lowest matching rank always
+        // belongs to the winning rule.
+        let nodes = config.ordered_rules();
+        for (index, node) in nodes.iter().enumerate() {
+            let category = Some(node.category.as_str());
+            validate(category, "patterns", &node.rule.patterns, &mut errors);
+            validate(category, "exclude", &node.rule.exclude, &mut errors);
+            parse_template(category, node.rule.template.as_deref(), &mut errors);
+            settings(category, node.rule.invalid_settings(), &mut errors);
+            positive.extend(node.rule.patterns.iter());
+            owners.extend(std::iter::repeat_n(index, node.rule.patterns.len()));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for node in &nodes {
+            // a parent's excludes also keep samples out of its children
+            let exclude = node
+                .ancestors
+                .iter()
+                .chain(std::iter::once(&node.rule))
+                .flat_map(|r| r.exclude.iter());
+            let gates = node
+                .ancestors
+                .iter()
+                .filter(|a| !a.patterns.is_empty())
+                .filter_map(|a| nodes.iter().position(|n| std::ptr::eq(n.rule, *a)))
+                .collect();
+            let conditions = node
+                .ancestors
+                .iter()
+                .chain(std::iter::once(&node.rule))
+                .filter_map(|r| Conditions::of(r))
+                .collect();
+            let template = std::iter::once(&node.rule)
+                .chain(node.ancestors.iter().rev())
+                .find_map(|r| r.template.as_deref())
+                .and_then(|t| Template::parse(t).ok())
+                .unwrap_or_else(|| default_template.clone());
+            let max_files_per_folder = std::iter::once(&node.rule)
+                .chain(node.ancestors.iter().rev())
+                .find_map(|r| r.max_files_per_folder)
+                .or(config.max_files_per_folder);
+            rules.push(CompiledRule {
+                category: Category {
+                    name: node.category.clone(),
+                    template,
+                    max_files_per_folder,
+                },
+                exclude: Matcher::build(Some(&node.category), "exclude", exclude, &mut errors),
+                gates,
+                conditions,
+                fuzzy: node.rule.fuzzy,
+                keywords: node
+                    .rule
+                    .patterns
+                    .iter()
+                    .filter(|p| matches!(p.kind, PatternKind::Keyword | PatternKind::Token))
+                    .map(|p| (p.target, tokenize(&p.pattern).join(" ")))
+                    .filter(|(_, keyword)| keyword.chars().count() >= MIN_FUZZY_KEYWORD_LEN)
+                    .collect(),
+            });
+        }
+
+        let fallback = config.classifier.as_ref().map(|classifier| Fallback {
+            min_confidence: classifier.min_confidence,
+            categories: SoundClass::ALL
+                .iter()
+                .map(|&class| {
+                    let name = classifier.category(class);
+                    // a class sorted into a rule's category shares its output settings
+                    rules
+                        .iter()
+                        .find(|r| r.category.name == name)
+                        .map(|r| r.category.clone())
+                        .unwrap_or_else(|| Category {
+                            name: name.to_string(),
+                            template: default_template.clone(),
+                            max_files_per_folder: config.max_files_per_folder,
+                        })
+                })
+                .collect(),
+        });
+
+        let exclude = Matcher::build(None, "exclude", &config.exclude, &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let patterns = Matcher::new(positive).map_err(|e| {
+            vec![PatternError {
+                category: None,
+                list: "patterns",
+                index: 0,
+                pattern: String::new(),
+                message: format!("failed to build rule set: {}", e),
+            }]
+        })?;
+        Ok(Categorizer {
+            patterns,
+            owners,
+            rules,
+            exclude,
+            fallback,
+        })
+    }
+
+    /// Categorizes the sample at `path`; `root` is the source directory that
+    /// [`Target::Path`] patterns are matched relative to.
+    pub fn categorize(&self, path: &Path, root: &Path) -> Option<&Category> {
+        self.decide(path, root).map(|d| d.category)
+    }
+
+    /// Like [`Categorizer::categorize`], but also tells whether the category
+    /// was only found through a fuzzy keyword or by the sample's content.
+    pub fn decide(&self, path: &Path, root: &Path) -> Option<Decision<'_>> {
+        let subject = Subject::new(path, root)?;
+        let hits = self.hits(&subject);
+        // The header is only read once a rule with conditions is reached.
+        let mut info = None;
+
+        let winner = self.winner(&hits, &subject, path, &mut info);
+        if self.exclude.is_match(&subject) {
+            return None;
+        }
+        let Some((winner, _)) = winner else {
+            let content = self.classify(path)?;
+            return Some(Decision {
+                category: self.fallback_category(&content)?,
+                fuzzy: None,
+                content: Some(content),
+            });
+        };
+        Some(Decision {
+            category: &self.rules[winner].category,
+            fuzzy: hits.fuzzy[winner].clone(),
+            content: None,
+        })
+    }
+
+    /// Like [`Categorizer::categorize`], but reports every rule whose patterns
+    /// matched, in evaluation order, and why each loser was passed over.
+    pub fn explain(&self, path: &Path, root: &Path) -> Option<Explanation<'_>> {
+        let subject = Subject::new(path, root)?;
+        let hits = self.hits(&subject);
+        let mut info = None;
+        let winner = self.winner(&hits, &subject, path, &mut info);
+        let mut matches = Vec::new();
+        let ranks = self.patterns.matches(&subject);
+
+        for (i, rule) in self.rules.iter().enumerate().filter(|(i, _)| hits.any[*i]) {
+            let skipped = match winner {
+                Some((w, _)) if w == i => None,
+                Some((_, true)) if !hits.exact[i] => Some(Skip::Fuzzy),
+                Some((w, _)) if w < i => Some(Skip::Shadowed),
+                Some((_, exact)) => {
+                    let hit = if exact { &hits.exact } else { &hits.any };
+                    self.skip_reason(i, hit, &subject, path, &mut info)
+                }
+                None => self.skip_reason(i, &hits.any, &subject, path, &mut info),
+            };
+            let patterns = ranks
+                .iter()
+                .filter(|&&rank| self.owners[rank] == i)
+                .map(|&rank| self.patterns.sources[rank].as_str())
+                .collect();
+            matches.push(RuleMatch {
+                category: &rule.category.name,
+                patterns,
+                fuzzy: hits.fuzzy[i].clone(),
+                skipped,
+            });
+        }
+
+        let content = winner.is_none().then(|| self.classify(path)).flatten();
+        let category = match winner {
+            Some((w, _)) => Some(&self.rules[w].category),
+            None => content.as_ref().and_then(|c| self.fallback_category(c)),
+        };
+        let global_exclude = category.and_then(|_| self.exclude.first_match(&subject));
+        Some(Explanation {
+            matches,
+            global_exclude,
+            decision: category.filter(|_| global_exclude.is_none()),
+            content,
+        })
+    }
+
+    /// Runs the content classifier on a sample no rule claimed, if it's
+    /// enabled. Unreadable and silent files aren't classified.
+    fn classify(&self, path: &Path) -> Option<Classification> {
+        self.fallback.as_ref()?;
+        classify(path).ok().flatten()
+    }
+
+    /// The category `content` is sorted into, if the classifier is confident
+    /// enough.
+    fn fallback_category(&self, content: &Classification) -> Option<&Category> {
+        let fallback = self.fallback.as_ref()?;
+        let index = SoundClass::ALL.iter().position(|&c| c == content.class)?;
+        (content.confidence >= fallback.min_confidence).then(|| &fallback.categories[index])
+    }
+
+    /// Index of the rule that claims the sample, and whether it matched
+    /// exactly. Fuzzy matches only count when no rule matches exactly.
+    fn winner(
+        &self,
+        hits: &Hits,
+        subject: &Subject,
+        path: &Path,
+        info: &mut Option<Option<AudioInfo>>,
+    ) -> Option<(usize, bool)> {
+        // Rules are stored children first, so the first eligible one is the
+        // most specific match.
+        let mut first_eligible = |hit: &[bool]| {
+            (0..self.rules.len())
+                .find(|&i| hit[i] && self.skip_reason(i, hit, subject, path, info).is_none())
+        };
+        if let Some(winner) = first_eligible(&hits.exact) {
+            return Some((winner, true));
+        }
+        if hits.fuzzy.iter().all(Option::is_none) {
+            return None;
+        }
+        first_eligible(&hits.any).map(|winner| (winner, false))
+    }
+
+    /// Which rules match the sample, exactly or fuzzily, by index.
+    fn hits(&self, subject: &Subject) -> Hits {
+        let mut exact = vec![false; self.rules.len()];
+        for rank in self.patterns.matches(subject) {
+            exact[self.owners[rank]] = true;
+        }
+        let fuzzy: Vec<_> = self
+            .rules
+            .iter()
+            .zip(&exact)
+            .map(|(rule, &exact)| {
+                if exact {
+                    None
+                } else {
+                    rule.fuzzy_match(subject)
+                }
+            })
+            .collect();
+        let any = exact
+            .iter()
+            .zip(&fuzzy)
+            .map(|(e, f)| *e || f.is_some())
+            .collect();
+        Hits { exact, any, fuzzy }
+    }
+
+    /// Why the matching rule at `index` can't claim the sample, if anything.
+    /// `info` caches the WAV header across calls.
+    fn skip_reason(
+        &self,
+        index: usize,
+        hit: &[bool],
+        subject: &Subject,
+        path: &Path,
+        info: &mut Option<Option<AudioInfo>>,
+    ) -> Option<Skip> {
+        let rule = &self.rules[index];
+        if let Some(&gate) = rule.gates.iter().find(|&&g| !hit[g]) {
+            return Some(Skip::Parent(self.rules[gate].category.name.clone()));
+        }
+        if let Some(pattern) = rule.exclude.first_match(subject) {
+            return Some(Skip::Excluded(pattern.to_string()));
+        }
+        if rule.conditions.is_empty() {
+            return None;
+        }
+        // unreadable files never satisfy a condition
+        let info = info.get_or_insert_with(|| read_audio_info(path).ok());
+        let accepted = info
+            .as_ref()
+            .is_some_and(|info| rule.conditions.iter().all(|c| c.accepts(info)));
+        (!accepted).then_some(Skip::Conditions)
+    }
+}
+
+/// The rules a sample matches, by rule index.
+struct Hits {
+    exact: Vec<bool>,
+    /// Exact or fuzzy.
+    any: Vec<bool>,
+    /// Best fuzzy keyword match of each rule without an exact match.
+    fuzzy: Vec<Option<FuzzyMatch>>,
+}
+
+/// The result of [`Categorizer::decide`].
+#[derive(Debug)]
+pub struct Decision<'a> {
+    pub category: &'a Category,
+    /// Set if the category was only found through a fuzzy keyword.
+    pub fuzzy: Option<FuzzyMatch>,
+    /// Set if no rule matched and the category was picked by the content
+    /// classifier.
+    pub content: Option<Classification>,
+}
+
+/// A keyword that matched a differently spelled word of a sample's name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    pub keyword: String,
+    /// The word of the sample it was matched to.
+    pub word: String,
+    /// Jaro-Winkler similarity, from 0 to 1.
+    pub score: f64,
+}
+
+impl fmt::Display for FuzzyMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} ~ {:?} ({:.2})",
+            self.keyword, self.word, self.score
+        )
+    }
+}
+
+/// The result of [`Categorizer::explain`].
+#[derive(Debug)]
+pub struct Explanation<'a> {
+    /// Rules with at least one matching pattern, in the order they're tried.
+    pub matches: Vec<RuleMatch<'a>>,
+    /// The global exclude pattern that overruled the winning rule, if any.
+    pub global_exclude: Option<&'a str>,
+    pub decision: Option<&'a Category>,
+    /// What the content classifier made of the sample if no rule claimed it
+    /// and the classifier is enabled.
+    pub content: Option<Classification>,
+}
+
+/// A rule whose patterns matched a sample.
+#[derive(Debug)]
+pub struct RuleMatch<'a> {
+    pub category: &'a str,
+    /// The rule's own patterns that matched, as written in the config.
+    pub patterns: Vec<&'a str>,
+    /// Set if the rule only matched through a fuzzy keyword.
+    pub fuzzy: Option<FuzzyMatch>,
+    /// Why the rule didn't claim the sample, or `None` for the winner.
+    pub skipped: Option<Skip>,
+}
+
+/// Why a rule whose patterns matched didn't claim the sample.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Skip {
+    /// A parent category with patterns of its own didn't match.
+    Parent(String),
+    /// The rule's or an ancestor's exclude pattern matched.
+    Excluded(String),
+    /// The audio conditions weren't met or the header couldn't be read.
+    Conditions,
+    /// The rule only matched fuzzily while another rule matched exactly.
+    Fuzzy,
+    /// An earlier rule already claimed the sample.
+    Shadowed,
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Skip::Parent(parent) => write!(f, "parent category '{}' did not match", parent),
+            Skip::Excluded(pattern) => write!(f, "excluded by {:?}", pattern),
+            Skip::Conditions => write!(f, "audio conditions not met"),
+            Skip::Fuzzy => write!(f, "only a fuzzy match, another rule matched exactly"),
+            Skip::Shadowed => write!(f, "an earlier rule already matched"),
+        }
+    }
+}
+
+/// Parses `template` (or the default template if it's `None`), recording a
+/// failure in `errors`.
+fn parse_template(
+    category: Option<&str>,
+    template: Option<&str>,
+    errors: &mut Vec<PatternError>,
+) -> Template {
+    let template = template.unwrap_or(DEFAULT_TEMPLATE);
+    Template::parse(template).unwrap_or_else(|message| {
+        errors.push(PatternError {
+            category: category.map(str::to_string),
+            list: "template",
+            index: 0,
+            pattern: template.to_string(),
+            message,
+        });
+        Template::parse(DEFAULT_TEMPLATE).expect("default template is valid")
+    })
+}
+
+/// Records settings found by [`Rule::invalid_settings`] and the like.
+fn settings(
+    category: Option<&str>,
+    invalid: impl IntoIterator<Item = InvalidSetting>,
+    errors: &mut Vec<PatternError>,
+) {
+    errors.extend(invalid.into_iter().map(|setting| PatternError {
+        category: category.map(str::to_string),
+        list: setting.key,
+        index: 0,
+        pattern: setting.value,
+        message: setting.message,
+    }));
+}
+
+/// Translates a config pattern into the regex it is matched with. Keywords and
+/// globs are lowercased like the texts they're matched against; token patterns
+/// are matched against the target's tokens instead of its text.
+pub fn pattern_regex(pattern: &Pattern) -> String {
+    match pattern.kind {
+        PatternKind::Keyword => regex::escape(&pattern.pattern.to_lowercase()),
+        // matched against the target's tokens joined by single spaces
+        PatternKind::Token => format!(
+            "(?:^| ){}(?: |$)",
+            regex::escape(&tokenize(&pattern.pattern).join(" "))
+        ),
+        PatternKind::Glob => glob_to_regex(&pattern.pattern.to_lowercase()),
+        PatternKind::Regex => pattern.pattern.clone(),
+    }
+}
+
+fn validate(
+    category: Option<&str>,
+    list: &'static str,
+    patterns: &[Pattern],
+    errors: &mut Vec<PatternError>,
+) {
+    for (index, pattern) in patterns.iter().enumerate() {
+        let message = match Regex::new(&pattern_regex(pattern)) {
+            Err(e) => e.to_string(),
+            Ok(_)
+                if pattern.kind == PatternKind::Token && tokenize(&pattern.pattern).is_empty() =>
+            {
+                "a token pattern needs at least one letter or digit".to_string()
+            }
+            Ok(_) => continue,
+        };
+        errors.push(PatternError {
+            category: category.map(str::to_string),
+            list,
+            index,
+            pattern: pattern.pattern.clone(),
+            message,
+        });
+    }
+}
+
+/// A list of patterns compiled into one [`RegexSet`] per target.
+#[derive(Debug, Default)]
+struct Matcher {
+    sets: Vec<TargetSet>,
+    /// The patterns as written in the config, by rank.
+    sources: Vec<String>,
+}
+
+#[derive(Debug)]
+struct TargetSet {
+    target: Target,
+    /// Whether `set` is matched against the target's tokens.
+    tokens: bool,
+    set: RegexSet,
+    /// Position in the original pattern list of each pattern in `set`.
+    ranks: Vec<usize>,
+}
+
+impl Matcher {
+    fn new<'a>(patterns: impl IntoIterator<Item = &'a Pattern>) -> Result<Self, regex::Error> {
+        // (target, tokens) -> regexes and their ranks
+        type Group = ((Target, bool), Vec<String>, Vec<usize>);
+        let mut by_target: Vec<Group> = Vec::new();
+        let mut sources = Vec::new();
+        for (rank, pattern) in patterns.into_iter().enumerate() {
+            sources.push(pattern.pattern.clone());
+            let key = (pattern.target, pattern.kind == PatternKind::Token);
+            let slot = match by_target.iter().position(|(k, _, _)| *k == key) {
+                Some(i) => i,
+                None => {
+                    by_target.push((key, Vec::new(), Vec::new()));
+                    by_target.len() - 1
+                }
+            };
+            by_target[slot].1.push(pattern_regex(pattern));
+            by_target[slot].2.push(rank);
+        }
+
+        let mut sets = Vec::new();
+        for ((target, tokens), patterns, ranks) in by_target {
+            sets.push(TargetSet {
+                target,
+                tokens,
+                set: RegexSet::new(patterns)?,
+                ranks,
+            });
+        }
+        Ok(Matcher { sets, sources })
+    }
+
+    /// Compiles already validated `patterns`, recording failures in `errors`.
+    fn build<'a>(
+        category: Option<&str>,
+        list: &'static str,
+        patterns: impl IntoIterator<Item = &'a Pattern>,
+        errors: &mut Vec<PatternError>,
+    ) -> Self {
+        Matcher::new(patterns).unwrap_or_else(|e| {
+            errors.push(PatternError {
+                category: category.map(str::to_string),
+                list,
+                index: 0,
+                pattern: String::new(),
+                message: format!("failed to build pattern set: {}", e),
+            });
+            Matcher::default()
+        })
+    }
+
+    /// Ranks of all matching patterns, lowest first.
+    fn matches(&self, subject: &Subject) -> Vec<usize> {
+        let mut ranks: Vec<usize> = self
+            .sets
+            .iter()
+            .flat_map(|s| {
+                s.set
+                    .matches(subject.get(s))
+                    .into_iter()
+                    .map(|i| s.ranks[i])
+            })
+            .collect();
+        ranks.sort_unstable();
+        ranks
+    }
+
+    fn is_match(&self, subject: &Subject) -> bool {
+        self.sets.iter().any(|s| s.set.is_match(subject.get(s)))
+    }
+
+    /// The lowest ranked matching pattern, as written in the config.
+    fn first_match(&self, subject: &Subject) -> Option<&str> {
+        let rank = *self.matches(subject).first()?;
+        Some(&self.sources[rank])
+    }
+}
+
+/// The lowercased texts a sample can be matched against, one per [`Target`],
+/// and their tokens joined by single spaces.
+struct Subject {
+    filename: String,
+    stem: String,
+    folder: String,
+    path: String,
+    tokens: [String; 4],
+}
+
+impl Subject {
+    fn new(path: &Path, root: &Path) -> Option<Self> {
+        let filename = path.file_name()?.to_str()?;
+        let stem = path.file_stem()?.to_string_lossy();
+        let folder = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|f| f.to_string_lossy())
+            .unwrap_or_default();
+        // Always use '/' so path patterns behave the same on every platform
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        // tokens need the original case to find camelCase boundaries
+        let tokens = [filename, &stem, &folder, &relative].map(|text| tokenize(text).join(" "));
+        Some(Subject {
+            filename: filename.to_lowercase(),
+            stem: stem.to_lowercase(),
+            folder: folder.to_lowercase(),
+            path: relative.to_lowercase(),
+            tokens,
+        })
+    }
+
+    fn text(&self, target: Target) -> &str {
+        match target {
+            Target::Filename => &self.filename,
+            Target::Stem => &self.stem,
+            Target::Folder => &self.folder,
+            Target::Path => &self.path,
+        }
+    }
+
+    fn tokens(&self, target: Target) -> &str {
+        let index = match target {
+            Target::Filename => 0,
+            Target::Stem => 1,
+            Target::Folder => 2,
+            Target::Path => 3,
+        };
+        &self.tokens[index]
+    }
+
+    /// The text `set` is matched against.
+    fn get(&self, set: &TargetSet) -> &str {
+        if set.tokens {
+            self.tokens(set.target)
+        } else {
+            self.text(set.target)
+        }
     }
-    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use crate::config::Rule;
+
+    fn name(category: Option<&Category>) -> Option<&str> {
+        category.map(|c| c.name.as_str())
+    }
+
+    fn rule(category: &str, priority: i32, patterns: &[&str]) -> Rule {
+        Rule {
+            category: category.to_string(),
+            priority,
+            patterns: patterns.iter().map(|p| Pattern::new(p)).collect(),
+            exclude: Vec::new(),
+            children: Vec::new(),
+            min_duration: None,
+            max_duration: None,
+            channels: None,
+            min_sample_rate: None,
+            max_sample_rate: None,
+            template: None,
+            max_files_per_folder: None,
+            fuzzy: None,
+        }
+    }
+
+    fn regex_rule(category: &str, patterns: &[&str]) -> Rule {
+        let mut rule = rule(category, 0, &[]);
+        rule.patterns = patterns
+            .iter()
+            .map(|p| Pattern::regex(p, Target::Filename))
+            .collect();
+        rule
+    }
 
     fn create_mock_config() -> Config {
-        let mut patterns = HashMap::new();
-        patterns.insert(
-            "drums".to_string(),
-            vec!["kick".to_string(), "snare".to_string()],
-        );
-        patterns.insert(
-            "synth".to_string(),
-            vec!["pad".to_string(), "lead".to_string()],
-        );
-        Config { patterns }
+        Config {
+            rules: vec![
+                rule("drums", 0, &["kick", "snare"]),
+                rule("synth", 0, &["pad", "lead"]),
+            ],
+            ..Default::default()
+        }
     }
 
     #[test]
     fn test_categorize_sample_match() {
         let config = create_mock_config();
         let path = Path::new("path/to/my_kick_sample.wav");
-        assert_eq!(categorize_sample(path, &config), Some("drums".to_string()));
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            Some("drums")
+        );
     }
 
     #[test]
     fn test_categorize_sample_no_match() {
         let config = create_mock_config();
         let path = Path::new("path/to/my_vocal_sample.wav");
-        assert_eq!(categorize_sample(path, &config), None);
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            None
+        );
     }
 
     #[test]
     fn test_categorize_sample_multiple_patterns() {
         let config = create_mock_config();
         let path = Path::new("path/to/my_snare_drum.wav");
-        assert_eq!(categorize_sample(path, &config), Some("drums".to_string()));
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            Some("drums")
+        );
     }
 
     #[test]
     fn test_categorize_sample_case_insensitivity() {
         let config = create_mock_config();
         let path = Path::new("path/to/My_Pad_Sound.wav");
-        assert_eq!(categorize_sample(path, &config), Some("synth".to_string()));
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            Some("synth")
+        );
     }
 
     #[test]
     fn test_categorize_sample_empty_filename() {
         let config = create_mock_config();
         let path = Path::new("path/to/"); // No filename
-        assert_eq!(categorize_sample(path, &config), None);
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_categorize_sample_first_rule_wins_on_tie() {
+        let config = Config {
+            rules: vec![rule("kicks", 0, &["kick"]), rule("808", 0, &["808"])],
+            ..Default::default()
+        };
+        let path = Path::new("path/to/808 Kick.wav");
+        for _ in 0..10 {
+            assert_eq!(
+                name(
+                    Categorizer::new(&config)
+                        .unwrap()
+                        .categorize(path, Path::new(""))
+                ),
+                Some("kicks")
+            );
+        }
+    }
+
+    #[test]
+    fn test_categorize_sample_priority_overrides_file_order() {
+        let config = Config {
+            rules: vec![rule("kicks", 0, &["kick"]), rule("808", 5, &["808"])],
+            ..Default::default()
+        };
+        let path = Path::new("path/to/808 Kick.wav");
+        assert_eq!(
+            name(
+                Categorizer::new(&config)
+                    .unwrap()
+                    .categorize(path, Path::new(""))
+            ),
+            Some("808")
+        );
+    }
+
+    #[test]
+    fn test_new_reports_every_invalid_pattern() {
+        let config = Config {
+            rules: vec![
+                regex_rule("drums", &["kick", "sn(are"]),
+                regex_rule("synth", &["[pad", "lead"]),
+            ],
+            ..Default::default()
+        };
+        let errors = Categorizer::new(&config).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            (errors[0].category.as_deref(), errors[0].index),
+            (Some("drums"), 1)
+        );
+        assert_eq!(errors[0].pattern, "sn(are");
+        assert_eq!(
+            (errors[1].category.as_deref(), errors[1].index),
+            (Some("synth"), 0)
+        );
+        assert!(errors[1]
+            .to_string()
+            .contains("invalid pattern #0 \"[pad\" in category 'synth'"));
+    }
+
+    #[test]
+    fn test_categorize_reuses_compiled_rules() {
+        let categorizer = Categorizer::new(&create_mock_config()).unwrap();
+        assert_eq!(
+            name(categorizer.categorize(Path::new("a/kick.wav"), Path::new("a"))),
+            Some("drums")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("b/lead.wav"), Path::new("b"))),
+            Some("synth")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("c/vox.wav"), Path::new("c"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_categorize_by_folder_and_path() {
+        let mut kicks = rule("kicks", 0, &[]);
+        kicks
+            .patterns
+            .push(Pattern::regex("^kicks?$", Target::Folder));
+        let mut hats = rule("hats", 0, &[]);
+        hats.patterns
+            .push(Pattern::regex("^drums/hats/", Target::Path));
+        let categorizer = Categorizer::new(&Config {
+            rules: vec![kicks, hats],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let root = Path::new("/packs/Pack A");
+        assert_eq!(
+            name(categorizer.categorize(Path::new("/packs/Pack A/Drums/Kicks/001.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("/packs/Pack A/Drums/Hats/002.wav"), root)),
+            Some("hats")
+        );
+        // the source folder itself is not part of the relative path
+        assert_eq!(
+            name(categorizer.categorize(
+                Path::new("/packs/Pack A/Hats/003.wav"),
+                Path::new("/packs/Pack A")
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn test_categorize_stem_excludes_extension() {
+        let mut wav = rule("wav", 0, &[]);
+        wav.patterns.push(Pattern::regex("wav$", Target::Stem));
+        let categorizer = Categorizer::new(&Config {
+            rules: vec![wav],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("x/kick.wav"), Path::new("x"))),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("x/newwav.wav"), Path::new("x"))),
+            Some("wav")
+        );
+    }
+
+    #[test]
+    fn test_categorize_rank_spans_targets() {
+        let mut folder = rule("by_folder", 0, &[]);
+        folder
+            .patterns
+            .push(Pattern::regex("kicks", Target::Folder));
+        let by_name = rule("by_name", 0, &["kick"]);
+        let categorizer = Categorizer::new(&Config {
+            rules: vec![folder, by_name],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("lib/kicks/kick 1.wav"), Path::new("lib"))),
+            Some("by_folder")
+        );
+    }
+
+    #[test]
+    fn test_categorize_rule_exclude_falls_through() {
+        let mut hats = rule("hats", 0, &["hat"]);
+        hats.exclude = vec![Pattern::new("chat"), Pattern::new("that")];
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.exclude = vec![Pattern::new("loop")];
+        let config = Config {
+            rules: vec![hats, kicks, rule("loops", 0, &["loop"])],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Open Hat.wav"), root)),
+            Some("hats")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Chatter.wav"), root)),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Kick 01.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Kick Loop 120.wav"), root)),
+            Some("loops")
+        );
+    }
+
+    #[test]
+    fn test_categorize_global_exclude() {
+        let config = Config {
+            rules: vec![rule("kicks", 0, &["kick"])],
+            exclude: vec![
+                Pattern::regex("^preview", Target::Filename),
+                Pattern::regex("^__macosx$", Target::Folder),
+            ],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("pack");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("pack/kick.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("pack/preview kick.wav"), root)),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("pack/__MACOSX/kick.wav"), root)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_new_reports_invalid_exclude_patterns() {
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.exclude = vec![
+            Pattern::regex("loop(", Target::Filename),
+            Pattern::new("fx"),
+        ];
+        let config = Config {
+            rules: vec![kicks],
+            exclude: vec![Pattern::new("ok"), Pattern::regex("[bad", Target::Filename)],
+            ..Default::default()
+        };
+        let errors = Categorizer::new(&config).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].category.as_deref(), errors[0].index), (None, 1));
+        assert!(errors[0]
+            .to_string()
+            .starts_with("invalid global exclude pattern #1 \"[bad\""));
+        assert_eq!(
+            (errors[1].category.as_deref(), errors[1].list),
+            (Some("kicks"), "exclude")
+        );
+        assert!(errors[1]
+            .to_string()
+            .starts_with("invalid exclude pattern #0 \"loop(\" in category 'kicks'"));
+    }
+
+    #[test]
+    fn test_categorize_keywords_are_literal() {
+        let config = Config {
+            rules: vec![rule("leads", 0, &["C++ Lead", "a.b"])],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("My C++ Lead 2.wav"), root)),
+            Some("leads")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("c lead.wav"), root)),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("axb.wav"), root)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_categorize_globs_match_whole_target() {
+        let mut bd = rule("kicks", 0, &[]);
+        bd.patterns = vec![
+            Pattern {
+                kind: PatternKind::Glob,
+                ..Pattern::new("BD_*.wav")
+            },
+            Pattern {
+                kind: PatternKind::Glob,
+                target: Target::Path,
+                ..Pattern::new("drums/kick*/**")
+            },
+        ];
+        let config = Config {
+            rules: vec![bd],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("lib");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("lib/bd_808.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("lib/old bd_808.wav"), root)),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("lib/Drums/Kicks/Hard/001.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("lib/Drums/Snares/001.wav"), root)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pattern_regex() {
+        assert_eq!(pattern_regex(&Pattern::new("C++ Lead")), "c\\+\\+ lead");
+        assert_eq!(
+            pattern_regex(&Pattern::regex("^Kick", Target::Filename)),
+            "^Kick"
+        );
+    }
+
+    #[test]
+    fn test_categorize_hierarchy_most_specific_wins() {
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.children = vec![rule("808", 0, &["808"])];
+        let mut hats = rule("hats", 0, &["hat"]);
+        hats.children = vec![rule("open", 0, &["open"])];
+        // no patterns of its own: only groups its children
+        let mut drums = rule("drums", 0, &[]);
+        drums.children = vec![kicks, hats];
+        let config = Config {
+            rules: vec![drums, rule("bass", 0, &["808", "bass"])],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("808 Kick.wav"), root)),
+            Some("drums/kicks/808")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Hard Kick.wav"), root)),
+            Some("drums/kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Open Hat.wav"), root)),
+            Some("drums/hats/open")
+        );
+        // no child matches, so the parent catches it
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Closed Hat.wav"), root)),
+            Some("drums/hats")
+        );
+        // children are only considered when their parent matches
+        assert_eq!(
+            name(categorizer.categorize(Path::new("808 Sub.wav"), root)),
+            Some("bass")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Open Snare.wav"), root)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_categorize_parent_exclude_applies_to_children() {
+        let mut drums = rule("drums", 0, &[]);
+        drums.exclude = vec![Pattern::new("loop")];
+        drums.children = vec![rule("kicks", 0, &["kick"])];
+        let config = Config {
+            rules: vec![drums, rule("loops", 0, &["loop"])],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Kick 01.wav"), root)),
+            Some("drums/kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Kick Loop.wav"), root)),
+            Some("loops")
+        );
+    }
+
+    #[test]
+    fn test_categorize_audio_conditions() {
+        use crate::audio_info::write_test_wav;
+        use tempfile::tempdir;
+
+        let mut one_shots = rule("one-shots", 0, &[]);
+        one_shots.max_duration = Some(2.0);
+        one_shots.children = vec![rule("kicks", 0, &["kick"])];
+        let mut loops = rule("loops", 0, &["kick", "loop"]);
+        loops.min_duration = Some(2.0);
+        let mut mono = rule("mono", 0, &["pad"]);
+        mono.channels = Some(1);
+        mono.min_sample_rate = Some(44100);
+        let config = Config {
+            rules: vec![one_shots, loops, mono],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let short_kick = root.join("kick 1.wav");
+        write_test_wav(&short_kick, 1, 8000, 4000);
+        let long_kick = root.join("kick 2.wav");
+        write_test_wav(&long_kick, 1, 8000, 32000);
+        let stereo_pad = root.join("pad 1.wav");
+        write_test_wav(&stereo_pad, 2, 44100, 10);
+        let lofi_pad = root.join("pad 2.wav");
+        write_test_wav(&lofi_pad, 1, 22050, 10);
+        let mono_pad = root.join("pad 3.wav");
+        write_test_wav(&mono_pad, 1, 48000, 10);
+        let broken = root.join("kick 3.wav");
+        std::fs::write(&broken, b"not a wav").unwrap();
+
+        assert_eq!(
+            name(categorizer.categorize(&short_kick, root)),
+            Some("one-shots/kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(&long_kick, root)),
+            Some("loops")
+        );
+        assert_eq!(name(categorizer.categorize(&stereo_pad, root)), None);
+        assert_eq!(name(categorizer.categorize(&lofi_pad, root)), None);
+        assert_eq!(name(categorizer.categorize(&mono_pad, root)), Some("mono"));
+        assert_eq!(name(categorizer.categorize(&broken, root)), None);
+    }
+
+    #[test]
+    fn test_new_rejects_folder_limit_of_zero() {
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.max_files_per_folder = Some(0);
+        let config = Config {
+            rules: vec![kicks],
+            max_files_per_folder: Some(0),
+            ..Default::default()
+        };
+        let errors: Vec<_> = Categorizer::new(&config)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "invalid global max_files_per_folder = 0: must be at least 1",
+                "invalid max_files_per_folder = 0 in category 'kicks': must be at least 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_impossible_conditions() {
+        let mut loops = rule("loops", 0, &["loop"]);
+        loops.min_duration = Some(4.0);
+        loops.max_duration = Some(2.0);
+        loops.channels = Some(0);
+        loops.min_sample_rate = Some(48000);
+        loops.max_sample_rate = Some(44100);
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.max_duration = Some(-1.0);
+        kicks.max_sample_rate = Some(0);
+        let mut silence = rule("silence", 0, &["silence"]);
+        silence.min_duration = Some(0.0);
+        silence.max_duration = Some(0.0);
+        let config = Config {
+            rules: vec![loops, kicks, silence],
+            ..Default::default()
+        };
+        let errors: Vec<_> = Categorizer::new(&config)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "invalid min_duration = 4.0 in category 'loops': \
+                 is greater than max_duration = 2.0, so it can never match",
+                "invalid channels = 0 in category 'loops': must be at least 1",
+                "invalid min_sample_rate = 48000 in category 'loops': \
+                 is greater than max_sample_rate = 44100, so it can never match",
+                "invalid max_duration = -1.0 in category 'kicks': must be a non-negative number of seconds",
+                "invalid max_sample_rate = 0 in category 'kicks': must be at least 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_templates_are_inherited() {
+        let mut drums = rule("drums", 0, &["drum"]);
+        drums.template = Some("{category}/{pack}/{filename}".to_string());
+        drums.children.push(rule("kicks", 0, &["kick"]));
+        let mut fx = rule("fx", 0, &["fx"]);
+        fx.template = Some("{stem:2}".to_string());
+        let config = Config {
+            rules: vec![drums, rule("bass", 0, &["bass"])],
+            template: Some("{filename}".to_string()),
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+
+        let kick = categorizer
+            .categorize(Path::new("drum kick.wav"), Path::new(""))
+            .unwrap();
+        assert_eq!(kick.name, "drums/kicks");
+        assert_eq!(
+            kick.template,
+            Template::parse("{category}/{pack}/{filename}").unwrap()
+        );
+        let bass = categorizer
+            .categorize(Path::new("bass.wav"), Path::new(""))
+            .unwrap();
+        assert_eq!(bass.template, Template::parse("{filename}").unwrap());
+
+        let errors = Categorizer::new(&Config {
+            rules: vec![fx],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "invalid template \"{stem:2}\" in category 'fx': '{stem}' takes no format"
+        );
+    }
+
+    #[test]
+    fn test_categorize_fuzzy_keywords() {
+        let mut kicks = rule("kicks", 0, &["kick"]);
+        kicks.fuzzy = Some(0.85);
+        let mut snares = rule("snares", 0, &["snare"]);
+        snares.fuzzy = Some(0.85);
+        let mut hats = rule("hats", 0, &["hi hat"]);
+        hats.fuzzy = Some(0.9);
+        let strict = rule("kits", 0, &["kit"]);
+        let config = Config {
+            rules: vec![kicks, snares, hats, strict],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("Kik_01.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("snar 2.wav"), root)),
+            Some("snares")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("hi hatt.wav"), root)),
+            Some("hats")
+        );
+        // an exact match elsewhere beats a fuzzy one
+        assert_eq!(
+            name(categorizer.categorize(Path::new("kik kit.wav"), root)),
+            Some("kits")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("kit.wav"), root)),
+            Some("kits")
+        );
+
+        let decision = categorizer.decide(Path::new("kik.wav"), root).unwrap();
+        let fuzzy = decision.fuzzy.unwrap();
+        assert_eq!(
+            (fuzzy.keyword.as_str(), fuzzy.word.as_str()),
+            ("kick", "kik")
+        );
+        assert!(fuzzy.score > 0.9 && fuzzy.score < 1.0);
+        assert!(categorizer
+            .decide(Path::new("kick.wav"), root)
+            .unwrap()
+            .fuzzy
+            .is_none());
+    }
+
+    #[test]
+    fn test_categorize_fuzzy_skips_short_keywords() {
+        let mut hats = rule("hats", 0, &["hihat"]);
+        hats.patterns.extend(["oh", "ch", "sd"].map(|p| Pattern {
+            pattern: p.to_string(),
+            kind: PatternKind::Token,
+            target: Target::Filename,
+        }));
+        hats.fuzzy = Some(0.9);
+        let config = Config {
+            rules: vec![hats],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        for file in ["ohm.wav", "Chr Stab.wav", "sdk.wav"] {
+            assert!(
+                categorizer.decide(Path::new(file), root).is_none(),
+                "{}",
+                file
+            );
+        }
+        assert_eq!(
+            name(categorizer.categorize(Path::new("hihatt.wav"), root)),
+            Some("hats")
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_fuzzy_outside_zero_to_one() {
+        for threshold in [2.0, -1.0, 0.0] {
+            let mut kicks = rule("kicks", 0, &["kick"]);
+            kicks.fuzzy = Some(threshold);
+            let config = Config {
+                rules: vec![kicks],
+                ..Default::default()
+            };
+            let errors = Categorizer::new(&config).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].to_string(),
+                format!(
+                    "invalid fuzzy = {:?} in category 'kicks': must be a similarity between 0 and 1",
+                    threshold
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_classifier_settings() {
+        let config = Config {
+            rules: vec![rule("kicks", 0, &["kick"])],
+            classifier: Some(crate::config::ClassifierConfig {
+                min_confidence: 1.5,
+                hat: "hats/".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let errors: Vec<_> = Categorizer::new(&config)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(
+            errors[0],
+            "invalid global classifier setting min_confidence = 1.5: must be between 0 and 1"
+        );
+        assert!(errors[1].starts_with(
+            "invalid global classifier setting hat = \"hats/\": is not a valid folder path"
+        ));
+    }
+
+    #[test]
+    fn test_categorize_whole_tokens() {
+        let token = |p: &str, target| Pattern {
+            pattern: p.to_string(),
+            kind: PatternKind::Token,
+            target,
+        };
+        let mut open_hats = rule("open_hats", 0, &[]);
+        open_hats.patterns = vec![
+            token("oh", Target::Filename),
+            token("open hat", Target::Stem),
+        ];
+        let mut kicks = rule("kicks", 0, &[]);
+        kicks.patterns = vec![token("bd", Target::Filename)];
+        kicks.fuzzy = Some(0.9);
+        let config = Config {
+            rules: vec![open_hats, kicks, rule("rides", 0, &["ride"])],
+            ..Default::default()
+        };
+        let categorizer = Categorizer::new(&config).unwrap();
+        let root = Path::new("");
+
+        assert_eq!(
+            name(categorizer.categorize(Path::new("OH_01.wav"), root)),
+            Some("open_hats")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("HatOH.wav"), root)),
+            Some("open_hats")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("OpenHat 2.wav"), root)),
+            Some("open_hats")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("hook.wav"), root)),
+            None
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("BD_808Long02.wav"), root)),
+            Some("kicks")
+        );
+        assert_eq!(
+            name(categorizer.categorize(Path::new("abdominal.wav"), root)),
+            None
+        );
+        // plain keywords still match anywhere
+        assert_eq!(
+            name(categorizer.categorize(Path::new("override.wav"), root)),
+            Some("rides")
+        );
+
+        let mut empty = rule("empty", 0, &[]);
+        empty.patterns = vec![token("__", Target::Filename)];
+        let errors = Categorizer::new(&Config {
+            rules: vec![empty],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(errors[0].message.contains("at least one letter or digit"));
+    }
+
+    #[test]
+    fn test_builtin_taxonomy() {
+        let config = crate::config::load_config(Path::new(crate::config::BUILTIN), None).unwrap();
+        let categorizer = Categorizer::new(&config).unwrap();
+        let cases = [
+            ("Kick_01.wav", Some("kicks")),
+            ("BD_808Long02.wav", Some("kicks")),
+            ("kik hard.wav", Some("kicks")),
+            ("SD 3.wav", Some("snares")),
+            ("Clap Tight.wav", Some("claps")),
+            ("OH_01.wav", Some("hats/open")),
+            ("ClosedHat 4.wav", Some("hats/closed")),
+            ("hihatt.wav", Some("hats")),
+            ("Tom Low.wav", Some("toms")),
+            ("Crash 1.wav", Some("cymbals")),
+            ("Shaker 2.wav", Some("percussion")),
+            ("Sub 808 C.wav", Some("bass")),
+            ("Lead Saw.wav", Some("leads")),
+            ("Warm Pad.wav", Some("pads")),
+            ("Vox Chop.wav", Some("vocals")),
+            ("Riser 8.wav", Some("fx")),
+            ("Kick Loop 120bpm.wav", Some("loops")),
+            ("override.wav", None),
+            ("chatter.wav", None),
+            // one letter away from a short name, and not a typo of it
+            ("That Thing.wav", None),
+            ("Chat.wav", None),
+            ("What.wav", None),
+            ("Chr Stab.wav", None),
+            ("Oho.wav", None),
+            ("Rime.wav", None),
+            ("Sdk.wav", None),
+            ("Nap.wav", None),
+            ("Cap.wav", None),
+        ];
+        for (file, expected) in cases {
+            assert_eq!(
+                name(categorizer.categorize(Path::new(file), Path::new(""))),
+                expected,
+                "{}",
+                file
+            );
+        }
     }
 }
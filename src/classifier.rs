@@ -0,0 +1,371 @@
+use crate::audio_info::read_mono;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+
+/// Seconds decoded from the start of a sample for analysis.
+const ANALYSIS_SECONDS: f64 = 2.0;
+/// Length of each analysed spectrum, in samples.
+const FFT_SIZE: usize = 2048;
+/// Most spectra averaged per sample.
+const MAX_FFT_FRAMES: usize = 8;
+/// Length of the blocks the amplitude envelope is measured in, in seconds.
+const ENVELOPE_BLOCK: f64 = 0.005;
+/// Upper edge of the band counted as low-frequency energy, in Hz.
+const LOW_BAND: f64 = 150.0;
+
+/// The kinds of sound the classifier can recognize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundClass {
+    Kick,
+    Snare,
+    Hat,
+    Percussion,
+    Tonal,
+}
+
+impl SoundClass {
+    pub const ALL: [SoundClass; 5] = [
+        SoundClass::Kick,
+        SoundClass::Snare,
+        SoundClass::Hat,
+        SoundClass::Percussion,
+        SoundClass::Tonal,
+    ];
+
+    /// The name used for this class in the config and in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundClass::Kick => "kick",
+            SoundClass::Snare => "snare",
+            SoundClass::Hat => "hat",
+            SoundClass::Percussion => "percussion",
+            SoundClass::Tonal => "tonal",
+        }
+    }
+}
+
+impl fmt::Display for SoundClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Measurements taken from the start of a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// Length of the whole file in seconds.
+    pub duration: f64,
+    /// Seconds from the onset to the loudest point.
+    pub attack: f64,
+    /// Brightness: the power-weighted mean frequency, in Hz.
+    pub centroid: f64,
+    /// Sign changes per second while the sound is audible; high for noise.
+    pub zero_crossing_rate: f64,
+    /// Share of the spectral power below 150 Hz, from 0 to 1.
+    pub low_energy: f64,
+}
+
+/// The sound class a sample most resembles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Classification {
+    pub class: SoundClass,
+    /// How well the features fit the class, from 0 to 1.
+    pub confidence: f64,
+    pub features: Features,
+}
+
+/// Classifies the WAV file at `path` by its content. Returns `None` for
+/// silent files.
+pub fn classify(path: &Path) -> Result<Option<Classification>, String> {
+    let (info, samples) = read_mono(path, ANALYSIS_SECONDS)?;
+    let features = analyze(&samples, info.sample_rate, info.duration());
+    Ok(features.map(|features| {
+        let (class, confidence) = SoundClass::ALL
+            .iter()
+            .map(|&class| (class, score(class, &features)))
+            // the first class wins ties
+            .fold((SoundClass::Kick, f64::MIN), |best, next| {
+                if next.1 > best.1 {
+                    next
+                } else {
+                    best
+                }
+            });
+        Classification {
+            class,
+            confidence,
+            features,
+        }
+    }))
+}
+
+/// Extracts the features of mono `samples`, or `None` if they're silent.
+fn analyze(samples: &[f32], sample_rate: u32, duration: f64) -> Option<Features> {
+    let rate = sample_rate as f64;
+    let block = ((rate * ENVELOPE_BLOCK) as usize).max(1);
+    let envelope: Vec<f32> = samples
+        .chunks(block)
+        .map(|c| c.iter().fold(0.0f32, |m, s| m.max(s.abs())))
+        .collect();
+    let peak = envelope.iter().copied().fold(0.0f32, f32::max);
+    if peak < 1e-4 {
+        return None;
+    }
+
+    let peak_block = envelope.iter().position(|&e| e == peak)?;
+    let onset = envelope.iter().position(|&e| e >= peak * 0.1)?;
+    // the sound counts as audible until it has decayed to 5% of its peak
+    let end = envelope.iter().rposition(|&e| e >= peak * 0.05)? + 1;
+    let audible = &samples[onset * block..(end * block).min(samples.len())];
+
+    let crossings = audible
+        .windows(2)
+        .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+        .count();
+    let (centroid, low_energy) = spectral_shape(&samples[onset * block..], rate)?;
+    Some(Features {
+        duration,
+        attack: (peak_block - onset) as f64 * block as f64 / rate,
+        centroid,
+        zero_crossing_rate: crossings as f64 * rate / audible.len().max(1) as f64,
+        low_energy,
+    })
+}
+
+/// Spectral centroid and low-band share of the power spectrum averaged over
+/// the first few windows of `samples`.
+fn spectral_shape(samples: &[f32], rate: f64) -> Option<(f64, f64)> {
+    let mut power = vec![0.0f64; FFT_SIZE / 2];
+    let frames = samples.len().div_ceil(FFT_SIZE).clamp(1, MAX_FFT_FRAMES);
+    for frame in 0..frames {
+        let mut re = vec![0.0f32; FFT_SIZE];
+        let mut im = vec![0.0f32; FFT_SIZE];
+        let start = frame * FFT_SIZE;
+        for (i, &s) in samples.iter().skip(start).take(FFT_SIZE).enumerate() {
+            let hann = 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos();
+            re[i] = s * hann;
+        }
+        fft(&mut re, &mut im);
+        for (bin, p) in power.iter_mut().enumerate() {
+            *p += (re[bin] * re[bin] + im[bin] * im[bin]) as f64;
+        }
+    }
+
+    let bin_width = rate / FFT_SIZE as f64;
+    let total: f64 = power.iter().skip(1).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let weighted: f64 = power
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(bin, p)| bin as f64 * bin_width * p)
+        .sum();
+    let low: f64 = power
+        .iter()
+        .enumerate()
+        .skip(1)
+        .take_while(|(bin, _)| *bin as f64 * bin_width < LOW_BAND)
+        .map(|(_, p)| p)
+        .sum();
+    Some((weighted / total, low / total))
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// How well `features` fit `class`, from 0 to 1: the geometric mean of how
+/// well each trait of the class is met, so a single missing trait rules the
+/// class out.
+fn score(class: SoundClass, f: &Features) -> f64 {
+    let short = ramp(f.duration, 2.0, 1.0);
+    let punchy = ramp(f.attack, 0.05, 0.01);
+    let traits = match class {
+        SoundClass::Kick => vec![
+            short,
+            punchy,
+            ramp(f.low_energy, 0.2, 0.6),
+            ramp(f.centroid, 1500.0, 400.0),
+        ],
+        SoundClass::Snare => vec![
+            short,
+            punchy,
+            ramp(f.centroid, 500.0, 1200.0) * ramp(f.centroid, 7000.0, 4500.0),
+            ramp(f.zero_crossing_rate, 500.0, 2000.0),
+            ramp(f.low_energy, 0.4, 0.1),
+        ],
+        SoundClass::Hat => vec![
+            ramp(f.duration, 1.5, 0.5),
+            ramp(f.centroid, 3000.0, 6000.0),
+            ramp(f.zero_crossing_rate, 2000.0, 6000.0),
+            ramp(f.low_energy, 0.2, 0.02),
+        ],
+        SoundClass::Percussion => vec![
+            short,
+            punchy,
+            ramp(f.centroid, 200.0, 500.0) * ramp(f.centroid, 4000.0, 2500.0),
+            ramp(f.zero_crossing_rate, 3000.0, 1500.0),
+            ramp(f.low_energy, 0.6, 0.3),
+        ],
+        SoundClass::Tonal => vec![
+            ramp(f.duration, 0.5, 1.5),
+            ramp(f.zero_crossing_rate, 3000.0, 800.0),
+        ],
+    };
+    traits
+        .iter()
+        .product::<f64>()
+        .powf(1.0 / traits.len() as f64)
+}
+
+/// 0 at `from`, 1 at `to` and linear in between; `from` may be above `to`
+/// for traits that call for low values.
+fn ramp(value: f64, from: f64, to: f64) -> f64 {
+    ((value - from) / (to - from)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_info::write_test_signal;
+    use tempfile::tempdir;
+
+    const RATE: u32 = 44100;
+
+    /// Deterministic white noise from -1 to 1.
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    fn decay(samples: &mut [f32], seconds: f32) {
+        for (i, s) in samples.iter_mut().enumerate() {
+            *s *= (-(i as f32) / RATE as f32 / seconds).exp();
+        }
+    }
+
+    fn sine(freq: f32, seconds: f32) -> Vec<f32> {
+        let len = (RATE as f32 * seconds) as usize;
+        (0..len)
+            .map(|i| 0.8 * (2.0 * PI * freq * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    fn classify_signal(samples: &[f32]) -> Option<Classification> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("001.wav");
+        write_test_signal(&path, RATE, samples);
+        classify(&path).unwrap()
+    }
+
+    #[test]
+    fn test_classify_kick() {
+        let mut kick = sine(55.0, 0.5);
+        decay(&mut kick, 0.1);
+
+        let result = classify_signal(&kick).unwrap();
+        assert_eq!(result.class, SoundClass::Kick);
+        assert!(result.confidence > 0.8, "{:?}", result);
+        assert!(result.features.low_energy > 0.6);
+        assert!(result.features.attack < 0.01);
+    }
+
+    #[test]
+    fn test_classify_hat() {
+        let mut hat = noise(RATE as usize / 4);
+        decay(&mut hat, 0.05);
+
+        let result = classify_signal(&hat).unwrap();
+        assert_eq!(result.class, SoundClass::Hat);
+        assert!(result.features.centroid > 6000.0, "{:?}", result);
+    }
+
+    #[test]
+    fn test_classify_snare() {
+        // noise through a one-pole low-pass filter at about 2 kHz
+        let mut snare = noise(RATE as usize * 2 / 5);
+        let mut y = 0.0;
+        for s in snare.iter_mut() {
+            y += 0.25 * (*s - y);
+            *s = y * 2.0;
+        }
+        decay(&mut snare, 0.08);
+
+        let result = classify_signal(&snare).unwrap();
+        assert_eq!(result.class, SoundClass::Snare, "{:?}", result);
+    }
+
+    #[test]
+    fn test_classify_tonal() {
+        let result = classify_signal(&sine(440.0, 2.5)).unwrap();
+        assert_eq!(result.class, SoundClass::Tonal);
+        assert!(result.confidence > 0.9, "{:?}", result);
+        assert_eq!(result.features.duration, 2.5);
+        assert!(
+            (result.features.centroid - 440.0).abs() < 30.0,
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_classify_silence() {
+        assert_eq!(classify_signal(&[0.0; 4410]), None);
+    }
+
+    #[test]
+    fn test_fft_finds_frequency() {
+        let mut re: Vec<f32> = (0..64)
+            .map(|i| (2.0 * PI * 4.0 * i as f32 / 64.0).cos())
+            .collect();
+        let mut im = vec![0.0; 64];
+        fft(&mut re, &mut im);
+        let loudest = (0..32)
+            .max_by(|&a, &b| re[a].abs().total_cmp(&re[b].abs()))
+            .unwrap();
+        assert_eq!(loudest, 4);
+        assert!((re[4] - 32.0).abs() < 1e-3);
+    }
+}
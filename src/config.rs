@@ -1,16 +1,613 @@
+use crate::classifier::SoundClass;
+use crate::utils::{invalid_folder_name_reason, line_col};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+/// Name accepted by `--config` and `include` for the embedded default
+/// taxonomy, in place of a file path.
+pub const BUILTIN: &str = "builtin";
+
+const BUILTIN_CONFIG: &str = include_str!("builtin.toml");
+
+/// Whether `path` refers to the embedded default taxonomy.
+pub fn is_builtin(path: &Path) -> bool {
+    path.as_os_str() == BUILTIN
+}
+
+/// A config file, or one `[profile.<name>]` section of it.
+///
+/// [`load_config`] resolves `include` and `profile` and returns a single
+/// merged config in which both are empty.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub patterns: HashMap<String, Vec<String>>,
+    /// Config files merged in before this one, relative to this file.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+    /// Samples matching any of these are never categorized.
+    #[serde(default)]
+    pub exclude: Vec<Pattern>,
+    /// Destination path template for rules that don't set their own, see
+    /// [`crate::template::Template`].
+    pub template: Option<String>,
+    /// Splits output folders holding more samples than this into numbered
+    /// folders, for rules that don't set their own limit.
+    pub max_files_per_folder: Option<usize>,
+    /// Sorts samples no rule matched by their audio content when present.
+    pub classifier: Option<ClassifierConfig>,
+    /// Named variations merged on top when selected with `--profile`.
+    #[serde(default)]
+    pub profile: BTreeMap<String, Config>,
+}
+
+/// A single `[[rule]]` entry. Rules are tried by descending `priority`;
+/// rules with equal priority keep the order in which they appear in the file.
+///
+/// Rules can be nested with `[[rule.child]]`. A child's category is a subfolder
+/// of its parent's. Children are only considered when their parent's patterns
+/// match (a parent without patterns just groups its children) and are tried
+/// before the parent, so the most specific match wins and the parent only
+/// catches what no child claimed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub category: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    /// A sample matching `patterns` is skipped by this rule and its children
+    /// if it also matches one of these; later rules still get a chance to
+    /// claim it.
+    #[serde(default)]
+    pub exclude: Vec<Pattern>,
+    #[serde(default, rename = "child")]
+    pub children: Vec<Rule>,
+    /// Audio properties read from the WAV header that a sample must have for
+    /// this rule and its children to apply. Durations are in seconds.
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    pub channels: Option<u16>,
+    pub min_sample_rate: Option<u32>,
+    pub max_sample_rate: Option<u32>,
+    /// Destination path template for this category and any children without
+    /// their own.
+    pub template: Option<String>,
+    /// Output folder limit for this category and any children without their
+    /// own.
+    pub max_files_per_folder: Option<usize>,
+    /// Enables typo-tolerant matching of this rule's keywords: a word whose
+    /// Jaro-Winkler similarity to a keyword reaches this threshold (0 to 1)
+    /// counts as a match when no rule matches exactly.
+    pub fuzzy: Option<f64>,
+}
+
+/// The `[classifier]` table: settings of the fallback that sorts samples no
+/// rule matched by what they sound like, see [`crate::classifier`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassifierConfig {
+    /// Lowest confidence (0 to 1) at which a sample is sorted by content;
+    /// samples below it stay uncategorized.
+    pub min_confidence: f64,
+    /// The category each sound class is sorted into.
+    pub kick: String,
+    pub snare: String,
+    pub hat: String,
+    pub percussion: String,
+    pub tonal: String,
+}
+
+impl Default for ClassifierConfig {
+    fn default() -> Self {
+        ClassifierConfig {
+            min_confidence: 0.5,
+            kick: "kicks".to_string(),
+            snare: "snares".to_string(),
+            hat: "hats".to_string(),
+            percussion: "percussion".to_string(),
+            tonal: "tonal".to_string(),
+        }
+    }
+}
+
+impl ClassifierConfig {
+    /// The category samples of `class` are sorted into.
+    pub fn category(&self, class: SoundClass) -> &str {
+        match class {
+            SoundClass::Kick => &self.kick,
+            SoundClass::Snare => &self.snare,
+            SoundClass::Hat => &self.hat,
+            SoundClass::Percussion => &self.percussion,
+            SoundClass::Tonal => &self.tonal,
+        }
+    }
+}
+
+/// A setting whose value can never work, found when rules are compiled and by
+/// `config check` alike.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidSetting {
+    /// The setting's key, e.g. `max_duration`.
+    pub key: &'static str,
+    /// The value as written, e.g. `-1` or `"hats/"`.
+    pub value: String,
+    /// What's wrong with it, e.g. `must be at least 1`.
+    pub message: String,
+}
+
+impl InvalidSetting {
+    fn new(key: &'static str, value: impl fmt::Debug, message: impl Into<String>) -> Self {
+        InvalidSetting {
+            key,
+            value: format!("{:?}", value),
+            message: message.into(),
+        }
+    }
+}
+
+/// The problem with a `max_files_per_folder` of `limit`, if there is one.
+pub fn invalid_limit(limit: Option<usize>) -> Option<InvalidSetting> {
+    (limit == Some(0)).then(|| InvalidSetting::new("max_files_per_folder", 0, "must be at least 1"))
+}
+
+impl Rule {
+    /// Settings of this rule, not its children, that can never work: audio
+    /// conditions no sample can meet, a fuzzy threshold outside 0 to 1 and a
+    /// folder limit of 0.
+    pub fn invalid_settings(&self) -> Vec<InvalidSetting> {
+        let mut invalid = Vec::new();
+        for (key, value) in [
+            ("min_duration", self.min_duration),
+            ("max_duration", self.max_duration),
+        ] {
+            if let Some(value) = value.filter(|v| *v < 0.0 || !v.is_finite()) {
+                invalid.push(InvalidSetting::new(
+                    key,
+                    value,
+                    "must be a non-negative number of seconds",
+                ));
+            }
+        }
+        if let Some((min, max)) = self
+            .min_duration
+            .zip(self.max_duration)
+            .filter(|(min, max)| min > max)
+        {
+            let message = format!(
+                "is greater than max_duration = {:?}, so it can never match",
+                max
+            );
+            invalid.push(InvalidSetting::new("min_duration", min, message));
+        }
+        if self.channels == Some(0) {
+            invalid.push(InvalidSetting::new("channels", 0, "must be at least 1"));
+        }
+        if self.max_sample_rate == Some(0) {
+            invalid.push(InvalidSetting::new(
+                "max_sample_rate",
+                0,
+                "must be at least 1",
+            ));
+        }
+        if let Some((min, max)) = self
+            .min_sample_rate
+            .zip(self.max_sample_rate)
+            .filter(|(min, max)| min > max)
+        {
+            let message = format!(
+                "is greater than max_sample_rate = {}, so it can never match",
+                max
+            );
+            invalid.push(InvalidSetting::new("min_sample_rate", min, message));
+        }
+        invalid.extend(invalid_limit(self.max_files_per_folder));
+        if let Some(threshold) = self.fuzzy.filter(|t| !(*t > 0.0 && *t <= 1.0)) {
+            invalid.push(InvalidSetting::new(
+                "fuzzy",
+                threshold,
+                "must be a similarity between 0 and 1",
+            ));
+        }
+        invalid
+    }
+}
+
+impl ClassifierConfig {
+    /// Settings that can never work: a `min_confidence` outside 0 to 1 and
+    /// categories that aren't valid folder paths.
+    pub fn invalid_settings(&self) -> Vec<InvalidSetting> {
+        let mut invalid = Vec::new();
+        if !(0.0..=1.0).contains(&self.min_confidence) {
+            invalid.push(InvalidSetting::new(
+                "min_confidence",
+                self.min_confidence,
+                "must be between 0 and 1",
+            ));
+        }
+        for class in SoundClass::ALL {
+            let category = self.category(class);
+            // classes may be sorted into nested categories like `drums/kicks`
+            if let Some(reason) = category.split('/').find_map(invalid_folder_name_reason) {
+                let message = format!("is not a valid folder path: {}", reason);
+                invalid.push(InvalidSetting::new(class.name(), category, message));
+            }
+        }
+        invalid
+    }
+}
+
+/// A rule placed in the category tree, as returned by [`Config::ordered_rules`].
+#[derive(Debug)]
+pub struct RuleNode<'a> {
+    /// Full category path, e.g. `drums/kicks/808`.
+    pub category: String,
+    pub rule: &'a Rule,
+    /// Parents of `rule`, outermost first.
+    pub ancestors: Vec<&'a Rule>,
+}
+
+/// A pattern, written either as a plain string (a keyword matched against the
+/// filename) or as `{ pattern = "...", kind = "...", target = "..." }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "RawPattern")]
+pub struct Pattern {
+    pub pattern: String,
+    pub kind: PatternKind,
+    pub target: Target,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPattern {
+    Plain(String),
+    Detailed(DetailedPattern),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetailedPattern {
+    pattern: String,
+    #[serde(default)]
+    kind: PatternKind,
+    #[serde(default)]
+    target: Target,
+}
+
+impl From<RawPattern> for Pattern {
+    fn from(raw: RawPattern) -> Self {
+        match raw {
+            RawPattern::Plain(pattern) => Pattern {
+                pattern,
+                kind: PatternKind::default(),
+                target: Target::default(),
+            },
+            RawPattern::Detailed(d) => Pattern {
+                pattern: d.pattern,
+                kind: d.kind,
+                target: d.target,
+            },
+        }
+    }
+}
+
+/// How the text of a pattern is interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternKind {
+    /// Literal text found anywhere in the target, e.g. `c++ lead`.
+    #[default]
+    Keyword,
+    /// Shell glob matched against the whole target, e.g. `bd_*.wav`.
+    Glob,
+    /// Regular expression, e.g. `^kicks?$`.
+    Regex,
+    /// Whole words of the target as split by [`crate::utils::tokenize`], so
+    /// `oh` matches `OH_01` and `HatOH` but not `hook`.
+    Token,
 }
 
-pub fn load_config(path: &Path) -> Result<Config, String> {
-    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
-    toml::from_str(&content).map_err(|e| e.to_string())
+/// The part of a sample's path a pattern is matched against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// File name including the extension, e.g. `001.wav`.
+    #[default]
+    Filename,
+    /// File name without the extension, e.g. `001`.
+    Stem,
+    /// Name of the folder containing the file, e.g. `kicks`.
+    Folder,
+    /// Path relative to the source directory, e.g. `drums/kicks/001.wav`.
+    Path,
+}
+
+impl PatternKind {
+    /// The name used for this kind in config files.
+    pub fn name(self) -> &'static str {
+        match self {
+            PatternKind::Keyword => "keyword",
+            PatternKind::Glob => "glob",
+            PatternKind::Regex => "regex",
+            PatternKind::Token => "token",
+        }
+    }
+}
+
+impl Target {
+    /// The name used for this target in config files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Filename => "filename",
+            Target::Stem => "stem",
+            Target::Folder => "folder",
+            Target::Path => "path",
+        }
+    }
+}
+
+#[cfg(test)]
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        Pattern {
+            pattern: pattern.to_string(),
+            kind: PatternKind::default(),
+            target: Target::default(),
+        }
+    }
+
+    pub fn regex(pattern: &str, target: Target) -> Self {
+        Pattern {
+            kind: PatternKind::Regex,
+            target,
+            ..Pattern::new(pattern)
+        }
+    }
+}
+
+impl Config {
+    /// Flattens the rule tree into the order rules must be evaluated in:
+    /// siblings by priority, each rule's children before the rule itself.
+    pub fn ordered_rules(&self) -> Vec<RuleNode<'_>> {
+        let mut nodes = Vec::new();
+        push_ordered(&self.rules, &[], "", &mut nodes);
+        nodes
+    }
+}
+
+impl Config {
+    /// Merges `layer` on top of `self`. A top-level rule replaces an existing
+    /// one with the same category in place; other rules are appended. Excludes
+    /// are added, and profiles of the same name are replaced.
+    pub fn merge(&mut self, layer: Config) {
+        for rule in layer.rules {
+            match self.rules.iter_mut().find(|r| r.category == rule.category) {
+                Some(existing) => *existing = rule,
+                None => self.rules.push(rule),
+            }
+        }
+        self.exclude.extend(layer.exclude);
+        self.include.extend(layer.include);
+        if layer.template.is_some() {
+            self.template = layer.template;
+        }
+        if layer.max_files_per_folder.is_some() {
+            self.max_files_per_folder = layer.max_files_per_folder;
+        }
+        if layer.classifier.is_some() {
+            self.classifier = layer.classifier;
+        }
+        self.profile.extend(layer.profile);
+    }
+}
+
+fn push_ordered<'a>(
+    rules: &'a [Rule],
+    ancestors: &[&'a Rule],
+    parent: &str,
+    nodes: &mut Vec<RuleNode<'a>>,
+) {
+    let mut siblings: Vec<&Rule> = rules.iter().collect();
+    // sort_by_key is stable, so file order breaks priority ties
+    siblings.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+    for rule in siblings {
+        let category = if parent.is_empty() {
+            rule.category.clone()
+        } else {
+            format!("{}/{}", parent, rule.category)
+        };
+        let mut lineage = ancestors.to_vec();
+        lineage.push(rule);
+        push_ordered(&rule.children, &lineage, &category, nodes);
+        nodes.push(RuleNode {
+            category,
+            rule,
+            ancestors: ancestors.to_vec(),
+        });
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The file is valid TOML but can't be used, e.g. an include cycle.
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            ConfigError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Loads the config at `path` with all its includes and, if given, merges the
+/// named profile on top.
+///
+/// Layers are merged in this order: the files listed in `include` (each with
+/// its own includes first), then the file itself, then the selected profile
+/// (again after its own includes).
+pub fn load_config(path: &Path, profile: Option<&str>) -> Result<Config, ConfigError> {
+    let mut config = load_layer(path, &mut Vec::new())?;
+
+    if let Some(name) = profile {
+        let Some(layer) = config.profile.remove(name) else {
+            let available: Vec<_> = config.profile.keys().map(String::as_str).collect();
+            return Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                message: format!(
+                    "unknown profile '{}' (available: {})",
+                    name,
+                    if available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                ),
+            });
+        };
+        config.merge(layer);
+    }
+    config.profile.clear();
+    Ok(config)
+}
+
+/// Loads one file and resolves its includes. `stack` holds the files currently
+/// being loaded, to detect include cycles.
+fn load_layer(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Config, ConfigError> {
+    let canonical = canonicalize(path)?;
+    if stack.contains(&canonical) {
+        let chain: Vec<_> = stack
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(ConfigError::Invalid {
+            path: path.to_path_buf(),
+            message: format!("include cycle: {}", chain.join(" -> ")),
+        });
+    }
+
+    let content = read_config(path)?;
+    let config = parse_config(path, &content)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    stack.push(canonical);
+    let resolved = resolve_includes(path, dir, config, stack);
+    stack.pop();
+    resolved
+}
+
+fn resolve_includes(
+    path: &Path,
+    dir: &Path,
+    mut layer: Config,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Config, ConfigError> {
+    let mut merged = Config::default();
+    for include in std::mem::take(&mut layer.include) {
+        merged.merge(load_layer(&include_path(dir, &include), stack)?);
+    }
+
+    for (name, profile) in std::mem::take(&mut layer.profile) {
+        if !profile.profile.is_empty() {
+            return Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                message: format!("profile '{}' can't contain other profiles", name),
+            });
+        }
+        let profile = resolve_includes(path, dir, profile, stack)?;
+        layer.profile.insert(name, profile);
+    }
+
+    merged.merge(layer);
+    Ok(merged)
+}
+
+/// Where an `include` entry of a file in `dir` points.
+pub fn include_path(dir: &Path, include: &Path) -> PathBuf {
+    if is_builtin(include) {
+        include.to_path_buf()
+    } else {
+        dir.join(include)
+    }
+}
+
+/// Identifies a config file for include cycle detection.
+pub fn canonicalize(path: &Path) -> Result<PathBuf, ConfigError> {
+    if is_builtin(path) {
+        return Ok(path.to_path_buf());
+    }
+    fs::canonicalize(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+pub fn read_config(path: &Path) -> Result<String, ConfigError> {
+    if is_builtin(path) {
+        return Ok(BUILTIN_CONFIG.to_string());
+    }
+    fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Parses `content`; `path` is only used to label errors.
+pub fn parse_config(path: &Path, content: &str) -> Result<Config, ConfigError> {
+    toml::from_str(content).map_err(|e| {
+        let legacy = content
+            .parse::<toml::Table>()
+            .is_ok_and(|table| table.contains_key("patterns"));
+        if legacy {
+            return ConfigError::Invalid {
+                path: path.to_path_buf(),
+                message: "the top-level [patterns] table is no longer supported; \
+                          write each category as a [[rule]] with `category` and `patterns`"
+                    .to_string(),
+            };
+        }
+        let (line, column) = e
+            .span()
+            .map(|span| line_col(content, span.start))
+            .unwrap_or((1, 1));
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: e.message().to_string(),
+        }
+    })
 }
 
 #[cfg(test)]
@@ -24,12 +621,18 @@ mod tests {
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("test_config.toml");
         let mut file = fs::File::create(&file_path).unwrap();
-        writeln!(file, "[patterns]\ncategory1 = [\"pattern1\"]\ncategory2 = [\"pattern2\"]").unwrap();
+        writeln!(
+            file,
+            "[[rule]]\ncategory = \"category1\"\npatterns = [\"pattern1\"]\n\n[[rule]]\ncategory = \"category2\"\npatterns = [\"pattern2\"]"
+        )
+        .unwrap();
 
-        let config = load_config(&file_path).unwrap();
-        assert_eq!(config.patterns.len(), 2);
-        assert_eq!(config.patterns["category1"], vec!["pattern1"]);
-        assert_eq!(config.patterns["category2"], vec!["pattern2"]);
+        let config = load_config(&file_path, None).unwrap();
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.rules[0].category, "category1");
+        assert_eq!(config.rules[0].patterns, vec![Pattern::new("pattern1")]);
+        assert_eq!(config.rules[1].category, "category2");
+        assert_eq!(config.rules[1].patterns, vec![Pattern::new("pattern2")]);
     }
 
     #[test]
@@ -37,9 +640,10 @@ mod tests {
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("non_existent_config.toml");
 
-        let err = load_config(&file_path).unwrap_err();
+        let err = load_config(&file_path, None).unwrap_err();
         eprintln!("Error: {}", err);
-        assert!(err.contains("os error 2"));
+        assert!(matches!(err, ConfigError::Io { .. }));
+        assert!(err.to_string().contains("os error 2"));
     }
 
     #[test]
@@ -47,9 +651,275 @@ mod tests {
         let dir = tempdir().unwrap();
         let file_path = dir.path().join("invalid_config.toml");
         let mut file = fs::File::create(&file_path).unwrap();
-        writeln!(file, "[patterns]\ncategory1 = \"pattern1\"").unwrap(); // Malformed TOML
+        writeln!(
+            file,
+            "[[rule]]\ncategory = \"category1\"\npatterns = \"pattern1\""
+        )
+        .unwrap(); // Malformed TOML
+
+        let err = load_config(&file_path, None).unwrap_err();
+        match err {
+            ConfigError::Parse { line, column, .. } => assert_eq!((line, column), (3, 12)),
+            other => panic!("expected parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ordered_rules_priority_then_file_order() {
+        let config: Config = toml::from_str(
+            "[[rule]]\ncategory = \"a\"\npatterns = []\n\n\
+             [[rule]]\ncategory = \"b\"\npriority = 10\npatterns = []\n\n\
+             [[rule]]\ncategory = \"c\"\npatterns = []\n\n\
+             [[rule]]\ncategory = \"d\"\npriority = 10\npatterns = []",
+        )
+        .unwrap();
+
+        let order: Vec<_> = config
+            .ordered_rules()
+            .iter()
+            .map(|n| n.category.clone())
+            .collect();
+        assert_eq!(order, vec!["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn test_load_config_rejects_legacy_patterns_table() {
+        let err =
+            parse_config(Path::new("config.toml"), "[patterns]\nkick = [\"kick\"]").unwrap_err();
+        assert!(matches!(&err, ConfigError::Invalid { .. }));
+        assert!(err.to_string().contains("[[rule]]"), "{}", err);
+
+        // a rule's own `patterns` is fine
+        let err = parse_config(
+            Path::new("config.toml"),
+            "[[rule]]\ncategory = 1\npatterns = []",
+        )
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }));
+    }
+
+    #[test]
+    fn test_load_config_pattern_targets() {
+        let config: Config = toml::from_str(
+            "[[rule]]\ncategory = \"kicks\"\n\
+             patterns = [\"kick\", { pattern = \"^kicks?$\", kind = \"regex\", target = \"folder\" }, { pattern = \"bd\" }]",
+        )
+        .unwrap();
+
+        let patterns = &config.rules[0].patterns;
+        assert_eq!(patterns[0], Pattern::new("kick"));
+        assert_eq!(patterns[1], Pattern::regex("^kicks?$", Target::Folder));
+        assert_eq!(patterns[2].target, Target::Filename);
+    }
+
+    #[test]
+    fn test_load_config_excludes() {
+        let config: Config = toml::from_str(
+            "exclude = [\"preview\"]\n\n\
+             [[rule]]\ncategory = \"hats\"\npatterns = [\"hat\"]\nexclude = [\"chat\", \"that\"]",
+        )
+        .unwrap();
+
+        assert_eq!(config.exclude, vec![Pattern::new("preview")]);
+        assert_eq!(
+            config.rules[0].exclude,
+            vec![Pattern::new("chat"), Pattern::new("that")]
+        );
+    }
+
+    #[test]
+    fn test_load_config_pattern_kinds() {
+        let config: Config = toml::from_str(
+            "[[rule]]\ncategory = \"leads\"\n\
+             patterns = [\"c++ lead\", { pattern = \"ld_*\", kind = \"glob\" }, { pattern = \"^lead\", kind = \"regex\" }, \
+             { pattern = \"ld\", kind = \"token\" }]",
+        )
+        .unwrap();
+
+        let kinds: Vec<_> = config.rules[0].patterns.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                PatternKind::Keyword,
+                PatternKind::Glob,
+                PatternKind::Regex,
+                PatternKind::Token
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_config_rejects_unknown_kind() {
+        let result = toml::from_str::<Config>(
+            "[[rule]]\ncategory = \"leads\"\npatterns = [{ pattern = \"ld\", kind = \"fuzzy\" }]",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_ordered_rules_children_before_parents() {
+        let config: Config = toml::from_str(
+            "[[rule]]\ncategory = \"drums\"\npatterns = [\"drum\"]\n\n\
+             [[rule.child]]\ncategory = \"hats\"\n\n\
+             [[rule.child.child]]\ncategory = \"open\"\npatterns = [\"open\"]\n\n\
+             [[rule.child]]\ncategory = \"kicks\"\npriority = 1\npatterns = [\"kick\"]\n\n\
+             [[rule]]\ncategory = \"fx\"\npatterns = [\"fx\"]",
+        )
+        .unwrap();
+
+        let nodes = config.ordered_rules();
+        let order: Vec<_> = nodes.iter().map(|n| n.category.as_str()).collect();
+        assert_eq!(
+            order,
+            vec![
+                "drums/kicks",
+                "drums/hats/open",
+                "drums/hats",
+                "drums",
+                "fx"
+            ]
+        );
+
+        let ancestors: Vec<_> = nodes[1]
+            .ancestors
+            .iter()
+            .map(|r| r.category.as_str())
+            .collect();
+        assert_eq!(ancestors, vec!["drums", "hats"]);
+        assert!(nodes[3].ancestors.is_empty());
+    }
+
+    #[test]
+    fn test_load_config_includes_and_profiles() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("shared")).unwrap();
+        fs::write(
+            dir.path().join("shared/base.toml"),
+            "exclude = [\"preview\"]\n\n\
+             [[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\n\n\
+             [[rule]]\ncategory = \"snares\"\npatterns = [\"snare\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("shared/mono.toml"),
+            "exclude = [\"stereo\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("device.toml"),
+            "include = [\"shared/base.toml\"]\n\n\
+             [[rule]]\ncategory = \"hats\"\npatterns = [\"hat\"]\n\n\
+             [profile.digitakt]\ninclude = [\"shared/mono.toml\"]\n\n\
+             [[profile.digitakt.rule]]\ncategory = \"kicks\"\npatterns = [\"kick\", \"bd\"]\n",
+        )
+        .unwrap();
+        let path = dir.path().join("device.toml");
+
+        let config = load_config(&path, None).unwrap();
+        let categories: Vec<_> = config.rules.iter().map(|r| r.category.as_str()).collect();
+        assert_eq!(categories, vec!["kicks", "snares", "hats"]);
+        assert_eq!(config.rules[0].patterns.len(), 1);
+        assert_eq!(config.exclude, vec![Pattern::new("preview")]);
+        assert!(config.include.is_empty() && config.profile.is_empty());
+
+        let config = load_config(&path, Some("digitakt")).unwrap();
+        let categories: Vec<_> = config.rules.iter().map(|r| r.category.as_str()).collect();
+        assert_eq!(categories, vec!["kicks", "snares", "hats"]);
+        assert_eq!(
+            config.rules[0].patterns,
+            vec![Pattern::new("kick"), Pattern::new("bd")]
+        );
+        assert_eq!(
+            config.exclude,
+            vec![Pattern::new("preview"), Pattern::new("stereo")]
+        );
+    }
+
+    #[test]
+    fn test_load_config_unknown_profile() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[profile.octatrack]\n[profile.laptop]\n").unwrap();
+
+        let err = load_config(&path, Some("digitakt")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown profile 'digitakt' (available: laptop, octatrack)"));
+    }
+
+    #[test]
+    fn test_load_config_include_cycle() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        fs::write(dir.path().join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let err = load_config(&dir.path().join("a.toml"), None).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid { .. }));
+        assert!(err.to_string().contains("include cycle"));
+    }
+
+    #[test]
+    fn test_load_config_missing_include() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "include = [\"missing.toml\"]\n").unwrap();
+
+        let err = load_config(&path, None).unwrap_err();
+        match err {
+            ConfigError::Io { path, .. } => assert!(path.ends_with("missing.toml")),
+            other => panic!("expected io error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_config_audio_conditions() {
+        let config: Config = toml::from_str(
+            "[[rule]]\ncategory = \"one-shots\"\nmax_duration = 2.0\nchannels = 1\nmin_sample_rate = 44100\n\n\
+             [[rule.child]]\ncategory = \"kicks\"\npatterns = [\"kick\"]",
+        )
+        .unwrap();
+
+        let rule = &config.rules[0];
+        assert_eq!(rule.max_duration, Some(2.0));
+        assert_eq!(rule.channels, Some(1));
+        assert_eq!(rule.min_sample_rate, Some(44100));
+        assert_eq!((rule.min_duration, rule.max_sample_rate), (None, None));
+        assert_eq!(rule.children[0].max_duration, None);
+    }
+
+    #[test]
+    fn test_load_config_builtin_can_be_extended() {
+        let builtin = load_config(Path::new(BUILTIN), None).unwrap();
+        let categories: Vec<_> = builtin.rules.iter().map(|r| r.category.as_str()).collect();
+        assert!(categories.contains(&"kicks") && categories.contains(&"vocals"));
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "include = [\"builtin\"]\n\n[[rule]]\ncategory = \"kicks\"\npatterns = [\"thump\"]\n\n\
+             [[rule]]\ncategory = \"foley\"\npatterns = [\"foley\"]\n",
+        )
+        .unwrap();
+        let config = load_config(&path, None).unwrap();
+        assert_eq!(config.rules.len(), builtin.rules.len() + 1);
+        let kicks = config.rules.iter().find(|r| r.category == "kicks").unwrap();
+        assert_eq!(kicks.patterns, vec![Pattern::new("thump")]);
+        assert_eq!(config.rules.last().unwrap().category, "foley");
+    }
+
+    #[test]
+    fn test_load_config_classifier() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.classifier, None);
+
+        let config: Config =
+            toml::from_str("[classifier]\nmin_confidence = 0.7\nhat = \"drums/hats\"").unwrap();
+        let classifier = config.classifier.unwrap();
+        assert_eq!(classifier.min_confidence, 0.7);
+        assert_eq!(classifier.category(SoundClass::Hat), "drums/hats");
+        assert_eq!(classifier.category(SoundClass::Kick), "kicks");
 
-        let err = load_config(&file_path).unwrap_err();
-        assert!(err.contains("TOML parse error"));
+        assert!(toml::from_str::<Config>("[classifier]\nclap = \"claps\"").is_err());
     }
 }
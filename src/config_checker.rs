@@ -0,0 +1,778 @@
+use crate::categorizer::pattern_regex;
+use crate::config::{
+    canonicalize, include_path, invalid_limit, parse_config, read_config, Config, ConfigError,
+    InvalidSetting, Pattern, PatternKind, Rule, Target,
+};
+use crate::template::Template;
+use crate::utils::{invalid_folder_name_reason, line_col, tokenize};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a config file, located by 1-based line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The file the problem is in; may be an included file.
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            severity,
+            self.message
+        )
+    }
+}
+
+/// One step of a path into the TOML document, e.g. `rule`, `2`, `patterns`.
+#[derive(Debug, Clone, Copy)]
+enum Key<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+fn join_key<'a>(base: &[Key<'a>], rest: &[Key<'a>]) -> Vec<Key<'a>> {
+    [base, rest].concat()
+}
+
+/// Parses and validates the config at `path` and every file it includes.
+///
+/// Read and syntax errors in `path` itself are returned as `Err`; everything
+/// else, including unreadable or broken included files, is reported as a list
+/// of diagnostics so all problems can be shown in one go.
+pub fn check_config(path: &Path) -> Result<Vec<Diagnostic>, ConfigError> {
+    let mut diagnostics = Vec::new();
+    check_file(path, &mut Vec::new(), &mut Vec::new(), &mut diagnostics)?;
+    Ok(diagnostics)
+}
+
+/// `stack` holds the files currently being checked, `done` every file checked
+/// so far, so shared includes are only reported once.
+fn check_file(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    done: &mut Vec<PathBuf>,
+    out: &mut Vec<Diagnostic>,
+) -> Result<(), ConfigError> {
+    let content = read_config(path)?;
+    let config = parse_config(path, &content)?;
+    let document = ImDocument::parse(content.as_str()).map_err(|e| {
+        let (line, column) = e
+            .span()
+            .map(|span| line_col(&content, span.start))
+            .unwrap_or((1, 1));
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: e.message().to_string(),
+        }
+    })?;
+
+    let mut checker = Checker {
+        path,
+        content: &content,
+        document: document.as_item(),
+        diagnostics: Vec::new(),
+    };
+    checker.check(&config);
+
+    let canonical = canonicalize(path)?;
+    stack.push(canonical.clone());
+    done.push(canonical);
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut nested = Vec::new();
+    for (include, key) in includes(&config) {
+        let include_path = include_path(dir, include);
+        match canonicalize(&include_path) {
+            Err(ConfigError::Io { source, .. }) => checker.report(
+                Severity::Error,
+                &key,
+                format!(
+                    "cannot read included file {}: {}",
+                    include_path.display(),
+                    source
+                ),
+            ),
+            Err(e) => checker.report(Severity::Error, &key, e.to_string()),
+            Ok(c) if stack.contains(&c) => checker.report(
+                Severity::Error,
+                &key,
+                format!(
+                    "include cycle: {} is already being included",
+                    include_path.display()
+                ),
+            ),
+            Ok(c) if done.contains(&c) => {}
+            Ok(_) => match check_file(&include_path, stack, done, &mut nested) {
+                Ok(()) => {}
+                Err(ConfigError::Parse {
+                    path,
+                    line,
+                    column,
+                    message,
+                }) => nested.push(Diagnostic {
+                    severity: Severity::Error,
+                    path,
+                    line,
+                    column,
+                    message,
+                }),
+                Err(e) => checker.report(Severity::Error, &key, e.to_string()),
+            },
+        }
+    }
+    stack.pop();
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    out.extend(diagnostics);
+    out.extend(nested);
+    Ok(())
+}
+
+/// All `include` entries of the file and of its profiles, with their location.
+fn includes(config: &Config) -> Vec<(&Path, Vec<Key<'_>>)> {
+    let mut found = Vec::new();
+    for (i, include) in config.include.iter().enumerate() {
+        found.push((
+            include.as_path(),
+            vec![Key::Field("include"), Key::Index(i)],
+        ));
+    }
+    for (name, profile) in &config.profile {
+        for (i, include) in profile.include.iter().enumerate() {
+            found.push((
+                include.as_path(),
+                vec![
+                    Key::Field("profile"),
+                    Key::Field(name),
+                    Key::Field("include"),
+                    Key::Index(i),
+                ],
+            ));
+        }
+    }
+    found
+}
+
+struct Checker<'a> {
+    path: &'a Path,
+    content: &'a str,
+    document: &'a Item,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, config: &Config) {
+        self.check_layer(config, &[]);
+        for (name, profile) in &config.profile {
+            let key = [Key::Field("profile"), Key::Field(name)];
+            for nested in profile.profile.keys() {
+                self.report(
+                    Severity::Error,
+                    &join_key(&key, &[Key::Field("profile"), Key::Field(nested)]),
+                    format!("profile '{}' can't contain other profiles", name),
+                );
+            }
+            self.check_layer(profile, &key);
+        }
+    }
+
+    /// Checks the excludes and rules of the file or of one profile at `key`.
+    fn check_layer<'c>(&mut self, layer: &'c Config, key: &[Key<'c>]) {
+        for (e, exclude) in layer.exclude.iter().enumerate() {
+            self.check_regex(
+                exclude,
+                &join_key(
+                    key,
+                    &[Key::Field("exclude"), Key::Index(e), Key::Field("pattern")],
+                ),
+                "global exclude",
+            );
+        }
+        self.check_template(
+            layer.template.as_deref(),
+            &join_key(key, &[Key::Field("template")]),
+            "the config",
+        );
+        self.check_settings(
+            invalid_limit(layer.max_files_per_folder)
+                .into_iter()
+                .collect(),
+            key,
+            "the config",
+        );
+        if let Some(classifier) = &layer.classifier {
+            self.check_settings(
+                classifier.invalid_settings(),
+                &join_key(key, &[Key::Field("classifier")]),
+                "the classifier",
+            );
+        }
+        self.check_rules(&layer.rules, &join_key(key, &[Key::Field("rule")]), "");
+    }
+
+    /// Checks a group of sibling rules at `key` and, recursively, their children.
+    fn check_rules<'c>(&mut self, rules: &'c [Rule], key: &[Key<'c>], parent: &str) {
+        // Duplicates only matter between siblings: children of different
+        // parents never compete for the same sample.
+        // (regex, target, is token) -> (category, location) of its first use
+        let mut seen: HashMap<(String, Target, bool), (String, Vec<Key<'c>>)> = HashMap::new();
+
+        for (r, rule) in rules.iter().enumerate() {
+            let rule_key = join_key(key, &[Key::Index(r)]);
+            let category = if parent.is_empty() {
+                rule.category.clone()
+            } else {
+                format!("{}/{}", parent, rule.category)
+            };
+
+            if let Some(reason) = invalid_folder_name_reason(&rule.category) {
+                self.report(
+                    Severity::Error,
+                    &join_key(&rule_key, &[Key::Field("category")]),
+                    format!(
+                        "category '{}' is not a valid folder name: {}",
+                        rule.category, reason
+                    ),
+                );
+            }
+
+            if rule.patterns.is_empty() && rule.children.is_empty() {
+                self.report(
+                    Severity::Error,
+                    &join_key(&rule_key, &[Key::Field("patterns")]),
+                    format!(
+                        "category '{}' has no patterns or child rules and can never match",
+                        category
+                    ),
+                );
+            }
+
+            for (p, pattern) in rule.patterns.iter().enumerate() {
+                let location = join_key(
+                    &rule_key,
+                    &[Key::Field("patterns"), Key::Index(p), Key::Field("pattern")],
+                );
+                self.check_regex(pattern, &location, &format!("category '{}'", category));
+                // keyword "kick" and regex "kick" are the same rule, so compare compiled forms
+                let key = (
+                    pattern_regex(pattern),
+                    pattern.target,
+                    pattern.kind == PatternKind::Token,
+                );
+                let pattern = &pattern.pattern;
+
+                match seen.get(&key) {
+                    Some((first, _)) if *first == category => self.report(
+                        Severity::Warning,
+                        &location,
+                        format!("duplicate pattern {:?} in category '{}'", pattern, category),
+                    ),
+                    Some((first, first_location)) => {
+                        let (line, column) = self.locate(first_location).unwrap_or((1, 1));
+                        let message = format!(
+                            "duplicate pattern {:?}, already used by category '{}' at {}:{}",
+                            pattern, first, line, column
+                        );
+                        self.report(Severity::Warning, &location, message);
+                    }
+                    None => {
+                        seen.insert(key, (category.clone(), location));
+                    }
+                }
+            }
+
+            self.check_settings(
+                rule.invalid_settings(),
+                &rule_key,
+                &format!("category '{}'", category),
+            );
+            self.check_template(
+                rule.template.as_deref(),
+                &join_key(&rule_key, &[Key::Field("template")]),
+                &format!("category '{}'", category),
+            );
+            self.check_fuzzy(rule, &rule_key, &category);
+
+            for (e, exclude) in rule.exclude.iter().enumerate() {
+                self.check_regex(
+                    exclude,
+                    &join_key(
+                        &rule_key,
+                        &[Key::Field("exclude"), Key::Index(e), Key::Field("pattern")],
+                    ),
+                    &format!("exclude of category '{}'", category),
+                );
+            }
+
+            self.check_rules(
+                &rule.children,
+                &join_key(&rule_key, &[Key::Field("child")]),
+                &category,
+            );
+        }
+    }
+
+    fn check_template(&mut self, template: Option<&str>, location: &[Key], owner: &str) {
+        if let Some(Err(e)) = template.map(Template::parse) {
+            self.report(
+                Severity::Error,
+                location,
+                format!(
+                    "invalid template {:?} in {}: {}",
+                    template.unwrap_or_default(),
+                    owner,
+                    e
+                ),
+            );
+        }
+    }
+
+    /// Reports the settings at `key` that can never work, as found by the
+    /// same checks the categorizer runs.
+    fn check_settings(&mut self, invalid: Vec<InvalidSetting>, key: &[Key], owner: &str) {
+        for setting in invalid {
+            self.report(
+                Severity::Error,
+                &join_key(key, &[Key::Field(setting.key)]),
+                format!(
+                    "invalid {} = {} in {}: {}",
+                    setting.key, setting.value, owner, setting.message
+                ),
+            );
+        }
+    }
+
+    fn check_fuzzy(&mut self, rule: &Rule, key: &[Key], category: &str) {
+        let Some(threshold) = rule.fuzzy else {
+            return;
+        };
+        // thresholds outside 0 to 1 are reported by `check_settings`
+        if threshold > 0.0
+            && threshold <= 1.0
+            && !rule
+                .patterns
+                .iter()
+                .any(|p| matches!(p.kind, PatternKind::Keyword | PatternKind::Token))
+        {
+            self.report(
+                Severity::Warning,
+                &join_key(key, &[Key::Field("fuzzy")]),
+                format!(
+                    "fuzzy has no effect in category '{}', which has no keyword patterns",
+                    category
+                ),
+            );
+        }
+    }
+
+    fn check_regex(&mut self, pattern: &Pattern, location: &[Key], owner: &str) {
+        if let Err(e) = Regex::new(&pattern_regex(pattern)) {
+            // regex errors are multi-line with a caret diagram; the last
+            // line carries the actual reason
+            let message = e.to_string();
+            let reason = message
+                .lines()
+                .last()
+                .unwrap_or_default()
+                .trim_start_matches("error: ");
+            self.report(
+                Severity::Error,
+                location,
+                format!(
+                    "invalid {} {:?} in {}: {}",
+                    pattern.kind.name(),
+                    pattern.pattern,
+                    owner,
+                    reason
+                ),
+            );
+        } else if pattern.kind == PatternKind::Token && tokenize(&pattern.pattern).is_empty() {
+            self.report(
+                Severity::Error,
+                location,
+                format!(
+                    "token {:?} in {} has no letters or digits and can never match",
+                    pattern.pattern, owner
+                ),
+            );
+        }
+    }
+
+    fn report(&mut self, severity: Severity, key: &[Key], message: String) {
+        let (line, column) = self.locate(key).unwrap_or((1, 1));
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path: self.path.to_path_buf(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    /// Resolves `key` to the position of the innermost item that exists.
+    fn locate(&self, key: &[Key]) -> Option<(usize, usize)> {
+        let mut item = self.document;
+        let mut span = None;
+        for step in key {
+            let next = match step {
+                Key::Field(name) => item.get(name),
+                Key::Index(i) => item.get(i),
+            };
+            match next {
+                Some(next) => {
+                    item = next;
+                    span = item.span().or(span);
+                }
+                None => break,
+            }
+        }
+        span.map(|s| line_col(self.content, s.start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn check_str(content: &str) -> Result<Vec<Diagnostic>, ConfigError> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, content).unwrap();
+        check_config(&path)
+    }
+
+    #[test]
+    fn test_check_config_clean() {
+        let diagnostics =
+            check_str("[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\", \"bd\"]\n").unwrap();
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_check_config_invalid_regex_location() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\", { pattern = \"b(d\", kind = \"regex\" }]\n",
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let d = &diagnostics[0];
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!((d.line, d.column), (3, 33));
+        assert!(
+            d.message
+                .starts_with("invalid regex \"b(d\" in category 'kicks'"),
+            "{}",
+            d.message
+        );
+        assert!(d.message.contains("unclosed group"), "{}", d.message);
+    }
+
+    #[test]
+    fn test_check_config_empty_category_and_bad_name() {
+        let diagnostics = check_str("[[rule]]\ncategory = \"hats?\"\npatterns = []\n").unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 12));
+        assert!(diagnostics[0].message.contains("not a valid folder name"));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (3, 12));
+        assert!(diagnostics[1]
+            .message
+            .contains("has no patterns or child rules"));
+    }
+
+    #[test]
+    fn test_check_config_duplicate_patterns() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\", \"kick\"]\n\n\
+             [[rule]]\ncategory = \"drums\"\npatterns = [\"kick\"]\n",
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 21));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (7, 13));
+        assert!(diagnostics[1]
+            .message
+            .contains("already used by category 'kicks' at 3:13"));
+    }
+
+    #[test]
+    fn test_check_config_syntax_error_is_located() {
+        let err = check_str("[[rule]]\ncategory = kicks\npatterns = [\"kick\"]\n").unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ConfigError::Parse {
+                    line: 2,
+                    column: 12,
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_check_config_detailed_pattern_location() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"kicks\"\n\
+             patterns = [\"kicks\", { target = \"folder\", pattern = \"kick(s\", kind = \"regex\" }, { pattern = \"kicks\", target = \"folder\" }]\n",
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 53));
+    }
+
+    #[test]
+    fn test_check_config_invalid_excludes() {
+        let diagnostics = check_str(
+            "exclude = [{ pattern = \"(\", kind = \"regex\" }]\n\n\
+             [[rule]]\ncategory = \"hats\"\npatterns = [\"hat\"]\nexclude = [\"chat\", { pattern = \"[\", kind = \"regex\" }]\n",
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 24));
+        assert!(diagnostics[0].message.contains("in global exclude"));
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (6, 32));
+        assert!(diagnostics[1]
+            .message
+            .contains("in exclude of category 'hats'"));
+    }
+
+    #[test]
+    fn test_check_config_keyword_and_regex_duplicates() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"leads\"\npatterns = [\"c++\", { pattern = \"c\\\\+\\\\+\", kind = \"regex\" }]\n",
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_check_config_tokens() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"hats\"\n\
+             patterns = [{ pattern = \"OH\", kind = \"token\" }, { pattern = \"oh\", kind = \"token\" }, \
+             \"oh\", { pattern = \"--\", kind = \"token\" }]\n",
+        )
+        .unwrap();
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.column, d.severity)).collect();
+        assert_eq!(
+            found,
+            [(61, Severity::Warning), (103, Severity::Error)],
+            "{:?}",
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn test_check_config_nested_rules() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"drums\"\n\n\
+             [[rule.child]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\n\n\
+             [[rule.child.child]]\ncategory = \"808:\"\npatterns = [{ pattern = \"8(\", kind = \"regex\" }]\n\n\
+             [[rule.child]]\ncategory = \"toms\"\npatterns = [\"kick\"]\n\n\
+             [[rule]]\ncategory = \"bass\"\npatterns = [\"kick\"]\n",
+        )
+        .unwrap();
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert_eq!(messages[0].0, 9);
+        assert!(messages[0].1.contains("'808:' is not a valid folder name"));
+        assert_eq!(messages[1].0, 10);
+        assert!(messages[1].1.contains("in category 'drums/kicks/808:'"));
+        // siblings compete for the same samples, unrelated subtrees don't
+        assert_eq!(messages[2].0, 14);
+        assert!(messages[2]
+            .1
+            .contains("already used by category 'drums/kicks' at 6:13"));
+    }
+
+    #[test]
+    fn test_check_config_profiles() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\n\n\
+             [profile.digitakt]\nexclude = [{ pattern = \"(\", kind = \"regex\" }]\n\n\
+             [[profile.digitakt.rule]]\ncategory = \"kicks\"\npatterns = []\n\n\
+             [profile.digitakt.profile.nested]\n",
+        )
+        .unwrap();
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![6, 10, 12], "{:?}", diagnostics);
+        assert!(diagnostics[0].message.contains("in global exclude"));
+        assert!(diagnostics[1].message.contains("has no patterns"));
+        assert!(diagnostics[2]
+            .message
+            .contains("can't contain other profiles"));
+    }
+
+    #[test]
+    fn test_check_config_follows_includes() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("base.toml"),
+            "[[rule]]\ncategory = \"kicks\"\npatterns = [{ pattern = \"(\", kind = \"regex\" }]\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("broken.toml"), "[[rule]\n").unwrap();
+        fs::write(
+            dir.path().join("config.toml"),
+            "include = [\"base.toml\", \"missing.toml\", \"config.toml\"]\n\n\
+             [profile.laptop]\ninclude = [\"broken.toml\", \"base.toml\"]\n",
+        )
+        .unwrap();
+
+        let diagnostics = check_config(&dir.path().join("config.toml")).unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.path.file_name().unwrap().to_str().unwrap(),
+                    d.line,
+                    d.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("config.toml", 1, 25),
+                ("config.toml", 1, 41),
+                ("base.toml", 3, 25),
+                ("broken.toml", 1, 7)
+            ],
+            "{:?}",
+            diagnostics
+        );
+        assert!(diagnostics[0].message.contains("cannot read included file"));
+        assert!(diagnostics[1].message.contains("include cycle"));
+    }
+
+    #[test]
+    fn test_check_config_audio_conditions() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"loops\"\npatterns = [\"loop\"]\n\
+             min_duration = 4.0\nmax_duration = 2.0\nchannels = 0\n\
+             min_sample_rate = 48000\nmax_sample_rate = 44100\n\n\
+             [[rule]]\ncategory = \"shots\"\npatterns = [\"shot\"]\nmax_duration = -1.0\n",
+        )
+        .unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(found.len(), 4, "{:?}", found);
+        assert_eq!(found[0].0, 4);
+        assert!(found[0].1.contains(
+            "invalid min_duration = 4.0 in category 'loops': is greater than max_duration = 2.0"
+        ));
+        assert_eq!(found[1].0, 6);
+        assert!(found[1].1.contains("channels"));
+        assert_eq!(found[2].0, 7);
+        assert!(found[2].1.contains("greater than max_sample_rate = 44100"));
+        assert_eq!(found[3].0, 13);
+        assert!(found[3].1.contains("must be a non-negative number"));
+    }
+
+    #[test]
+    fn test_check_config_templates() {
+        let diagnostics = check_str(
+            "template = \"{category}/{bpm}.wav\"\n\n\
+             [[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\ntemplate = \"{stem:03}\"\n\n\
+             [profile.flat]\ntemplate = \"{filename}\"\nmax_files_per_folder = 0\n",
+        )
+        .unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.column, d.message.as_str()))
+            .collect();
+        assert_eq!(found.len(), 3, "{:?}", found);
+        assert_eq!((found[0].0, found[0].1), (1, 12));
+        assert!(found[0]
+            .2
+            .contains("in the config: unknown placeholder '{bpm}'"));
+        assert_eq!((found[1].0, found[1].1), (6, 12));
+        assert!(found[1]
+            .2
+            .contains("in category 'kicks': '{stem}' takes no format"));
+        assert_eq!((found[2].0, found[2].1), (10, 24));
+        assert!(found[2]
+            .2
+            .contains("invalid max_files_per_folder = 0 in the config: must be at least 1"));
+    }
+
+    #[test]
+    fn test_check_config_fuzzy() {
+        let diagnostics = check_str(
+            "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\nfuzzy = 1.5\n\n\
+             [[rule]]\ncategory = \"hats\"\npatterns = [{ pattern = \"^hat\", kind = \"regex\" }]\nfuzzy = 0.9\n",
+        )
+        .unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!((found[0].0, found[0].1), (4, Severity::Error));
+        assert!(found[0].2.contains("between 0 and 1"));
+        assert_eq!((found[1].0, found[1].1), (9, Severity::Warning));
+        assert!(found[1].2.contains("no keyword patterns"));
+    }
+
+    #[test]
+    fn test_check_config_builtin_is_clean() {
+        assert_eq!(check_config(Path::new("builtin")).unwrap(), Vec::new());
+        assert_eq!(check_str("include = [\"builtin\"]\n").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_check_config_classifier() {
+        let diagnostics = check_str(
+            "[classifier]\nmin_confidence = 1.5\nkick = \"drums/kicks\"\nhat = \"hats/\"\n",
+        )
+        .unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!(found[0].0, 2);
+        assert!(found[0].1.contains("between 0 and 1"));
+        assert_eq!(found[1].0, 4);
+        assert!(found[1]
+            .1
+            .contains("invalid hat = \"hats/\" in the classifier: is not a valid folder path"));
+    }
+}
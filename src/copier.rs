@@ -1,47 +1,367 @@
-use std::fs;
-use std::path::Path;
+use crate::progress;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
 
-pub fn copy_to_dest(sample_path: &Path, dest_root: &Path, category: &str) -> Result<(), String> {
-    let file_name = match sample_path.file_name() {
-        Some(name) => name,
-        None => return Err("Filename not found.".to_string()),
+/// How samples are transferred into the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Copy the file.
+    #[default]
+    Copy,
+    /// Move the file, removing it from the source.
+    Move,
+    /// Create a symbolic link to the original file.
+    Symlink,
+    /// Create a hard link to the original file; needs the same filesystem.
+    Hardlink,
+    /// Create a copy-on-write clone; needs a filesystem that supports it.
+    Reflink,
+}
+
+impl Mode {
+    /// The name used for this mode on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Copy => "copy",
+            Mode::Move => "move",
+            Mode::Symlink => "symlink",
+            Mode::Hardlink => "hardlink",
+            Mode::Reflink => "reflink",
+        }
+    }
+
+    /// What happened to a file in this mode, for reports.
+    pub fn past_tense(self) -> &'static str {
+        match self {
+            Mode::Copy => "Copied",
+            Mode::Move => "Moved",
+            Mode::Symlink => "Symlinked",
+            Mode::Hardlink => "Hard-linked",
+            Mode::Reflink => "Reflinked",
+        }
+    }
+}
+
+/// What to do when a file already exists where a sample should go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Keep the existing file and leave the sample out.
+    #[default]
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Place the sample next to it with a numeric suffix, e.g. `Kick (2).wav`.
+    Rename,
+    /// Replace the existing file only if the sample was modified later.
+    Newer,
+    /// Skip byte-identical files and rename the others.
+    Compare,
+}
+
+/// What was done about a file that already existed at the destination.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolution {
+    /// The existing file was kept and the sample left out.
+    Skipped,
+    /// The existing file has the same contents as the sample, or is the
+    /// sample itself.
+    Identical,
+    /// The existing file was modified at the same time as the sample or later.
+    NotNewer,
+    Overwritten,
+    /// The sample was placed at this path instead.
+    Renamed(PathBuf),
+}
+
+impl Resolution {
+    /// Whether the sample was left out.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Resolution::Skipped | Resolution::Identical | Resolution::NotNewer
+        )
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolution::Skipped => write!(f, "skipped, a file with that name exists"),
+            Resolution::Identical => write!(f, "skipped, an identical file exists"),
+            Resolution::NotNewer => write!(f, "skipped, the existing file is not older"),
+            Resolution::Overwritten => write!(f, "overwrote the existing file"),
+            Resolution::Renamed(path) => {
+                write!(f, "renamed to {:?}", path.file_name().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Decides what `on_conflict` does with `sample_path` if `dest_file` is
+/// already taken, either on disk or in `planned`, which maps destinations of
+/// transfers that haven't been carried out yet to their samples. Returns
+/// `None` if `dest_file` is free.
+pub fn decide(
+    sample_path: &Path,
+    dest_file: &Path,
+    on_conflict: OnConflict,
+    planned: &HashMap<PathBuf, PathBuf>,
+) -> Result<Option<Resolution>, String> {
+    let taken = |path: &Path| planned.contains_key(path) || is_taken(path);
+    if !taken(dest_file) {
+        return Ok(None);
+    }
+    // the file that is, or is going to be, at the destination
+    let occupant = planned.get(dest_file).map_or(dest_file, PathBuf::as_path);
+    resolve(sample_path, occupant, dest_file, on_conflict, &taken)
+        .map(Some)
+        .map_err(|e| {
+            format!(
+                "Failed to compare {:?} with {:?}: {}",
+                sample_path, occupant, e
+            )
+        })
+}
+
+/// Transfers `sample_path` to `dest_file` in the given `mode` after its
+/// conflict, if any, was decided by [`decide`], creating missing parent
+/// folders. Fails rather than replacing a file that isn't meant to be
+/// overwritten.
+///
+/// If a link or clone can't be created, e.g. because the destination is on
+/// another filesystem, the sample is copied instead. Moves across
+/// filesystems copy the sample and then remove the original.
+pub fn carry_out(
+    sample_path: &Path,
+    dest_file: &Path,
+    resolution: Option<&Resolution>,
+    mode: Mode,
+) -> Result<(), String> {
+    if sample_path.file_name().is_none() {
+        return Err("Filename not found.".to_string());
+    }
+
+    let target = match resolution {
+        None | Some(Resolution::Overwritten) => dest_file,
+        Some(Resolution::Renamed(path)) => path.as_path(),
+        Some(Resolution::Skipped) => {
+            progress::println(format!(
+                "Skipping {:?}, {:?} already exists",
+                sample_path, dest_file
+            ));
+            return Ok(());
+        }
+        Some(skip) => {
+            progress::println(format!(
+                "Skipping {:?}, {:?}: {}",
+                sample_path, dest_file, skip
+            ));
+            return Ok(());
+        }
     };
+    if is_taken(target) {
+        if resolution != Some(&Resolution::Overwritten) {
+            return Err(format!("Not replacing {:?}, which already exists", target));
+        }
+        fs::remove_file(target).map_err(|e| format!("Failed to replace {:?}: {}", target, e))?;
+    }
 
-    let dest_dir = dest_root.join(category);
-    if !dest_dir.exists() {
-        fs::create_dir_all(&dest_dir).map_err(|e| format!("Failed to create folder {:?}: {}", dest_dir, e))?;
+    if let Some(dest_dir) = target.parent() {
+        if !dest_dir.exists() {
+            fs::create_dir_all(dest_dir)
+                .map_err(|e| format!("Failed to create folder {:?}: {}", dest_dir, e))?;
+        }
     }
+    place(sample_path, target, mode)
+}
 
-    let dest_file = dest_dir.join(file_name);
-    if dest_file.exists() {
-        println!(
-            "Skipping {:?}, file already exists in category '{}'",
-            sample_path, category
-        );
-        return Ok(());
+/// Whether something occupies `path`; a dangling symlink doesn't `exist`
+/// but still takes the name.
+fn is_taken(path: &Path) -> bool {
+    path.exists() || path.is_symlink()
+}
+
+/// Applies `policy` to a sample whose destination `dest_file` is taken by
+/// `occupant`. `taken` tells which other names are in use.
+fn resolve(
+    sample_path: &Path,
+    occupant: &Path,
+    dest_file: &Path,
+    policy: OnConflict,
+    taken: &dyn Fn(&Path) -> bool,
+) -> io::Result<Resolution> {
+    // a destination that is (or links to) the sample itself must never be
+    // replaced, or the sample would be deleted
+    if occupant.exists() && fs::canonicalize(sample_path)? == fs::canonicalize(occupant)? {
+        return Ok(Resolution::Identical);
     }
+    Ok(match policy {
+        OnConflict::Skip => Resolution::Skipped,
+        OnConflict::Overwrite => Resolution::Overwritten,
+        OnConflict::Rename => Resolution::Renamed(free_name(dest_file, taken)),
+        OnConflict::Newer => {
+            let sample_time = fs::metadata(sample_path)?.modified()?;
+            match fs::metadata(occupant).and_then(|m| m.modified()) {
+                Ok(existing_time) if existing_time >= sample_time => Resolution::NotNewer,
+                _ => Resolution::Overwritten,
+            }
+        }
+        OnConflict::Compare if occupant.exists() && same_contents(sample_path, occupant)? => {
+            Resolution::Identical
+        }
+        OnConflict::Compare => Resolution::Renamed(free_name(dest_file, taken)),
+    })
+}
+
+/// The first of `name (2).ext`, `name (3).ext`, ... next to `path` that is
+/// not taken.
+fn free_name(path: &Path, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+/// Whether `existing` is `sample` already placed: the sample itself, a link
+/// to it or a file with the same bytes.
+pub fn holds(existing: &Path, sample: &Path) -> bool {
+    let same_file = || Ok::<_, io::Error>(fs::canonicalize(existing)? == fs::canonicalize(sample)?);
+    same_file().unwrap_or(false) || same_contents(existing, sample).unwrap_or(false)
+}
 
-    fs::copy(sample_path, &dest_file)
+/// Whether the files at `a` and `b` have the same bytes.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let (mut a, mut b) = (
+        BufReader::new(File::open(a)?),
+        BufReader::new(File::open(b)?),
+    );
+    loop {
+        let chunk = a.fill_buf()?;
+        if chunk.is_empty() {
+            // equal lengths, so `b` is exhausted too
+            return Ok(true);
+        }
+        let len = chunk.len();
+        let mut other = vec![0; len];
+        b.read_exact(&mut other)?;
+        if chunk != other.as_slice() {
+            return Ok(false);
+        }
+        a.consume(len);
+    }
+}
+
+/// Puts the sample at the free path `dest_file`.
+fn place(sample_path: &Path, dest_file: &Path, mode: Mode) -> Result<(), String> {
+    let linked = match mode {
+        Mode::Copy => None,
+        Mode::Move => Some(fs::rename(sample_path, dest_file)),
+        Mode::Symlink => Some(symlink(sample_path, dest_file)),
+        Mode::Hardlink => Some(fs::hard_link(sample_path, dest_file)),
+        Mode::Reflink => Some(reflink_copy::reflink(sample_path, dest_file)),
+    };
+    match linked {
+        Some(Ok(())) => {
+            progress::println(format!(
+                "{} {:?} to {:?}",
+                mode.past_tense(),
+                sample_path,
+                dest_file
+            ));
+            return Ok(());
+        }
+        // renaming fails across filesystems, where a move is a copy and delete
+        Some(Err(_)) if mode == Mode::Move => {}
+        Some(Err(e)) => progress::println(format!(
+            "Could not {} {:?} ({}), copying it instead",
+            mode.name(),
+            sample_path,
+            e
+        )),
+        None => {}
+    }
+
+    fs::copy(sample_path, dest_file)
         .map_err(|e| format!("Failed to copy {:?}: {}", sample_path, e))?;
-    println!("Copied {:?} to {}", sample_path, category);
+    if mode == Mode::Move {
+        fs::remove_file(sample_path)
+            .map_err(|e| format!("Copied but failed to remove {:?}: {}", sample_path, e))?;
+        progress::println(format!("Moved {:?} to {:?}", sample_path, dest_file));
+    } else {
+        progress::println(format!("Copied {:?} to {:?}", sample_path, dest_file));
+    }
     Ok(())
 }
 
+/// Links `dest_file` to the absolute path of `sample_path`, so the link stays
+/// valid wherever the destination is viewed from.
+fn symlink(sample_path: &Path, dest_file: &Path) -> io::Result<()> {
+    let target = fs::canonicalize(sample_path)?;
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, dest_file);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target, dest_file);
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported",
+    ));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Write;
     use tempfile::tempdir;
 
+    /// Decides and carries out one transfer, the way `apply` does.
+    fn transfer_to_dest(
+        sample_path: &Path,
+        dest_file: &Path,
+        mode: Mode,
+        on_conflict: OnConflict,
+    ) -> Result<Option<Resolution>, String> {
+        let resolution = decide(sample_path, dest_file, on_conflict, &HashMap::new())?;
+        carry_out(sample_path, dest_file, resolution.as_ref(), mode)?;
+        Ok(resolution)
+    }
+
+    fn copy(sample_path: &Path, dest_file: &Path) -> Result<Option<Resolution>, String> {
+        transfer_to_dest(sample_path, dest_file, Mode::Copy, OnConflict::Skip)
+    }
+
     #[test]
-    fn test_copy_to_dest_success() {
+    fn test_transfer_to_dest_success() {
         let src_dir = tempdir().unwrap();
         let dest_root = tempdir().unwrap();
         let sample_path = src_dir.path().join("test_sample.wav");
-        fs::File::create(&sample_path).unwrap().write_all(b"test content").unwrap();
+        fs::File::create(&sample_path)
+            .unwrap()
+            .write_all(b"test content")
+            .unwrap();
 
         let category = "test_category";
-        copy_to_dest(&sample_path, dest_root.path(), category).unwrap();
+        copy(
+            &sample_path,
+            &dest_root.path().join(category).join("test_sample.wav"),
+        )
+        .unwrap();
 
         let expected_dest_file = dest_root.path().join(category).join("test_sample.wav");
         assert!(expected_dest_file.exists());
@@ -49,32 +369,69 @@ mod tests {
     }
 
     #[test]
-    fn test_copy_to_dest_directory_creation() {
+    fn test_transfer_to_dest_directory_creation() {
         let src_dir = tempdir().unwrap();
         let dest_root = tempdir().unwrap();
         let sample_path = src_dir.path().join("test_sample.wav");
         fs::File::create(&sample_path).unwrap();
 
         let category = "new_category";
-        copy_to_dest(&sample_path, dest_root.path(), category).unwrap();
+        copy(
+            &sample_path,
+            &dest_root.path().join(category).join("test_sample.wav"),
+        )
+        .unwrap();
 
         let expected_dest_dir = dest_root.path().join(category);
         assert!(expected_dest_dir.is_dir());
     }
 
     #[test]
-    fn test_copy_to_dest_file_already_exists() {
+    fn test_transfer_to_dest_nested_folders() {
+        let src_dir = tempdir().unwrap();
+        let dest_root = tempdir().unwrap();
+        let sample_path = src_dir.path().join("808.wav");
+        fs::File::create(&sample_path).unwrap();
+
+        copy(
+            &sample_path,
+            &dest_root.path().join("drums/kicks/808/808.wav"),
+        )
+        .unwrap();
+
+        assert!(dest_root
+            .path()
+            .join("drums")
+            .join("kicks")
+            .join("808")
+            .join("808.wav")
+            .exists());
+    }
+
+    #[test]
+    fn test_transfer_to_dest_file_already_exists() {
         let src_dir = tempdir().unwrap();
         let dest_root = tempdir().unwrap();
         let sample_path = src_dir.path().join("test_sample.wav");
-        fs::File::create(&sample_path).unwrap().write_all(b"original content").unwrap();
+        fs::File::create(&sample_path)
+            .unwrap()
+            .write_all(b"original content")
+            .unwrap();
 
         let category = "existing_category";
         let dest_dir = dest_root.path().join(category);
         fs::create_dir_all(&dest_dir).unwrap();
-        fs::File::create(dest_dir.join("test_sample.wav")).unwrap().write_all(b"existing content").unwrap();
+        fs::File::create(dest_dir.join("test_sample.wav"))
+            .unwrap()
+            .write_all(b"existing content")
+            .unwrap();
 
-        copy_to_dest(&sample_path, dest_root.path(), category).unwrap();
+        let resolution = copy(
+            &sample_path,
+            &dest_root.path().join(category).join("test_sample.wav"),
+        )
+        .unwrap();
+        assert_eq!(resolution, Some(Resolution::Skipped));
 
         let expected_dest_file = dest_root.path().join(category).join("test_sample.wav");
         assert!(expected_dest_file.exists());
@@ -82,14 +439,137 @@ mod tests {
     }
 
     #[test]
-    fn test_copy_to_dest_no_filename() {
+    fn test_transfer_to_dest_no_filename() {
         let dest_root = tempdir().unwrap();
         let sample_path = Path::new("/");
 
-        let result = copy_to_dest(&sample_path, dest_root.path(), "some_category");
+        let result = copy(
+            sample_path,
+            &dest_root.path().join("some_category").join("x.wav"),
+        );
         assert!(result.is_err());
         let err_msg = result.unwrap_err();
         assert_eq!(err_msg, "Filename not found.");
         assert!(!dest_root.path().join("some_category").exists());
     }
+
+    fn sample(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("kick.wav");
+        fs::write(&path, b"kick").unwrap();
+        path
+    }
+
+    #[test]
+    fn test_transfer_to_dest_move() {
+        let src_dir = tempdir().unwrap();
+        let dest_root = tempdir().unwrap();
+        let sample_path = sample(src_dir.path());
+        let dest_file = dest_root.path().join("kicks").join("kick.wav");
+
+        transfer_to_dest(&sample_path, &dest_file, Mode::Move, OnConflict::Skip).unwrap();
+        assert!(!sample_path.exists());
+        assert_eq!(fs::read(&dest_file).unwrap(), b"kick");
+    }
+
+    #[test]
+    fn test_transfer_to_dest_links() {
+        let src_dir = tempdir().unwrap();
+        let dest_root = tempdir().unwrap();
+        let sample_path = sample(src_dir.path());
+
+        for mode in [Mode::Symlink, Mode::Hardlink, Mode::Reflink] {
+            let dest_file = dest_root
+                .path()
+                .join(format!("{:?}", mode))
+                .join("kick.wav");
+            transfer_to_dest(&sample_path, &dest_file, mode, OnConflict::Skip).unwrap();
+            assert_eq!(fs::read(&dest_file).unwrap(), b"kick", "{:?}", mode);
+        }
+        assert!(sample_path.exists());
+        #[cfg(unix)]
+        {
+            let link = dest_root.path().join("Symlink").join("kick.wav");
+            assert_eq!(
+                fs::read_link(link).unwrap(),
+                fs::canonicalize(&sample_path).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_transfer_to_dest_keeps_dangling_symlink() {
+        let src_dir = tempdir().unwrap();
+        let dest_root = tempdir().unwrap();
+        let sample_path = sample(src_dir.path());
+        let dest_file = dest_root.path().join("kick.wav");
+        transfer_to_dest(&sample_path, &dest_file, Mode::Symlink, OnConflict::Skip).unwrap();
+        fs::remove_file(&sample_path).unwrap();
+
+        let other = src_dir.path().join("other.wav");
+        fs::write(&other, b"other").unwrap();
+        copy(&other, &dest_file).unwrap();
+        assert!(dest_file.is_symlink());
+    }
+
+    #[test]
+    fn test_transfer_to_dest_conflict_policies() {
+        let src_dir = tempdir().unwrap();
+        let dest_root = tempdir().unwrap();
+        let sample_path = sample(src_dir.path());
+        let dest_file = dest_root.path().join("kick.wav");
+        let transfer =
+            |policy| transfer_to_dest(&sample_path, &dest_file, Mode::Copy, policy).unwrap();
+
+        fs::write(&dest_file, b"other").unwrap();
+        let renamed = dest_root.path().join("kick (2).wav");
+        assert_eq!(
+            transfer(OnConflict::Rename),
+            Some(Resolution::Renamed(renamed.clone()))
+        );
+        assert_eq!(fs::read(&renamed).unwrap(), b"kick");
+        assert_eq!(fs::read(&dest_file).unwrap(), b"other");
+
+        // kick.wav still differs from the sample, so it goes to the next free name
+        let renamed_again = dest_root.path().join("kick (3).wav");
+        assert_eq!(
+            transfer(OnConflict::Compare),
+            Some(Resolution::Renamed(renamed_again.clone()))
+        );
+
+        // the existing file was written after the sample
+        assert_eq!(transfer(OnConflict::Newer), Some(Resolution::NotNewer));
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&sample_path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(transfer(OnConflict::Newer), Some(Resolution::Overwritten));
+        assert_eq!(fs::read(&dest_file).unwrap(), b"kick");
+
+        fs::write(&dest_file, b"other").unwrap();
+        assert_eq!(
+            transfer(OnConflict::Overwrite),
+            Some(Resolution::Overwritten)
+        );
+        assert_eq!(transfer(OnConflict::Compare), Some(Resolution::Identical));
+        assert!(!dest_root.path().join("kick (4).wav").exists());
+    }
+
+    #[test]
+    fn test_transfer_to_dest_never_replaces_the_sample_itself() {
+        let src_dir = tempdir().unwrap();
+        let sample_path = sample(src_dir.path());
+
+        let resolution = transfer_to_dest(
+            &sample_path,
+            &sample_path,
+            Mode::Copy,
+            OnConflict::Overwrite,
+        )
+        .unwrap();
+        assert_eq!(resolution, Some(Resolution::Identical));
+        assert_eq!(fs::read(&sample_path).unwrap(), b"kick");
+    }
 }
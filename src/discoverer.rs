@@ -1,12 +1,24 @@
+use crate::config::{parse_config, Pattern, PatternKind, Target};
+use crate::utils::{invalid_folder_name_reason, longest_common_prefix};
 use natord::compare;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table};
 use walkdir::WalkDir;
-use crate::utils::longest_common_prefix;
-use std::io::{self, Write};
 
-pub fn discover_patterns<W: Write>(writer: &mut W, source: &Path) -> io::Result<()> {
+/// Samples in one folder whose names share a prefix.
+#[derive(Debug, PartialEq)]
+pub struct PatternGroup {
+    pub prefix: String,
+    pub count: usize,
+}
+
+/// Groups the `.wav` files of every folder below `source` by common name
+/// prefix. Folders are returned in natural order.
+pub fn find_pattern_groups(source: &Path) -> Vec<(String, Vec<PatternGroup>)> {
     let mut temp_map: HashMap<String, Vec<String>> = HashMap::new();
 
     for entry in WalkDir::new(source).into_iter().filter_map(|e| e.ok()) {
@@ -33,9 +45,11 @@ pub fn discover_patterns<W: Write>(writer: &mut W, source: &Path) -> io::Result<
     let mut sorted_keys: Vec<_> = temp_map.keys().cloned().collect();
     sorted_keys.sort_by(|a, b| compare(a, b));
 
+    let mut folders = Vec::new();
     for key in sorted_keys {
-        let files = temp_map.remove(&key).unwrap_or_default();
-        writeln!(writer, "{}", key)?;
+        let mut files = temp_map.remove(&key).unwrap_or_default();
+        files.sort_by(|a, b| compare(a, b));
+        let mut groups = Vec::new();
 
         let mut ungrouped = files.clone();
         let mut seen = HashSet::new();
@@ -48,7 +62,8 @@ pub fn discover_patterns<W: Write>(writer: &mut W, source: &Path) -> io::Result<
             let mut i = 0;
             while i < ungrouped.len() {
                 let candidate = &ungrouped[i];
-                let potential_new_lcp = longest_common_prefix(&[current_lcp_for_group.clone(), candidate.clone()]);
+                let potential_new_lcp =
+                    longest_common_prefix(&[current_lcp_for_group.clone(), candidate.clone()]);
 
                 if potential_new_lcp.len() >= 4 {
                     group.push(ungrouped.remove(i));
@@ -64,16 +79,304 @@ pub fn discover_patterns<W: Write>(writer: &mut W, source: &Path) -> io::Result<
             }
 
             seen.insert(final_lcp.to_lowercase());
+            groups.push(PatternGroup {
+                prefix: final_lcp,
+                count: group.len(),
+            });
+        }
+        folders.push((key, groups));
+    }
+    folders
+}
+
+pub fn discover_patterns<W: Write>(writer: &mut W, source: &Path) -> io::Result<()> {
+    for (folder, groups) in find_pattern_groups(source) {
+        writeln!(writer, "{}", folder)?;
+        for group in groups {
             writeln!(
                 writer,
                 // TODO format printer
                 "- pattern like '{}*': {} files",
-                final_lcp,
-                group.len()
+                group.prefix, group.count
             )?;
         }
-
         writeln!(writer)?;
     }
     Ok(())
 }
+
+/// A rule proposed by `discover --emit-config` or `learn`.
+#[derive(Debug, PartialEq)]
+pub struct ProposedRule {
+    pub category: String,
+    pub patterns: Vec<Pattern>,
+    /// Written as a comment above the rule.
+    pub note: String,
+}
+
+/// Turns prefix groups into one proposed rule per category. A prefix like
+/// `Kick_0` becomes category `kick` with the pattern `^kick`; groups of a
+/// single file are ignored since their "prefix" is just the file name.
+fn propose_rules(folders: &[(String, Vec<PatternGroup>)]) -> Vec<ProposedRule> {
+    // category -> prefixes and file count, in discovery order
+    let mut found: Vec<(String, Vec<String>, usize)> = Vec::new();
+    for group in folders.iter().flat_map(|(_, groups)| groups) {
+        if group.count < 2 {
+            continue;
+        }
+        let prefix = clean_prefix(&group.prefix);
+        let category: String = prefix
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>()
+            .join("_");
+        if category.is_empty() || invalid_folder_name_reason(&category).is_some() {
+            continue;
+        }
+
+        match found.iter_mut().find(|(c, _, _)| *c == category) {
+            Some((_, prefixes, count)) => {
+                if !prefixes.contains(&prefix) {
+                    prefixes.push(prefix);
+                }
+                *count += group.count;
+            }
+            None => found.push((category, vec![prefix], group.count)),
+        }
+    }
+
+    found
+        .into_iter()
+        .map(|(category, prefixes, count)| ProposedRule {
+            category,
+            patterns: prefixes
+                .iter()
+                .map(|prefix| Pattern {
+                    pattern: format!("^{}", regex::escape(prefix)),
+                    kind: PatternKind::Regex,
+                    target: Target::Filename,
+                })
+                .collect(),
+            note: format!("discovered: {} files", count),
+        })
+        .collect()
+}
+
+/// Lowercases `prefix` and drops trailing separators and numbering, which
+/// usually belong to the individual files (`Kick_0` from `Kick_01`, `Kick_02`).
+pub fn clean_prefix(prefix: &str) -> String {
+    let lower = prefix.to_lowercase();
+    let trimmed = lower.trim_end_matches(|c: char| !c.is_alphanumeric());
+    let without_number = trimmed
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_end_matches(|c: char| !c.is_alphanumeric());
+    if without_number.is_empty() {
+        trimmed.to_string()
+    } else {
+        without_number.to_string()
+    }
+}
+
+/// Adds a `[[rule]]` for every proposed category not already in `existing`,
+/// keeping the rest of the document, comments included, as it was. Returns
+/// the new document and the number of rules added.
+pub fn merge_into_config(
+    existing: &str,
+    rules: &[ProposedRule],
+) -> Result<(String, usize), String> {
+    let mut document: DocumentMut = existing.parse().map_err(|e| format!("{}", e))?;
+    let taken: HashSet<String> = match document.get("rule").and_then(Item::as_array_of_tables) {
+        Some(tables) => tables
+            .iter()
+            .filter_map(|t| t.get("category").and_then(Item::as_str))
+            .map(str::to_string)
+            .collect(),
+        None => HashSet::new(),
+    };
+    if document.get("rule").is_none() {
+        document.insert("rule", Item::ArrayOfTables(ArrayOfTables::new()));
+    }
+    let tables = document["rule"]
+        .as_array_of_tables_mut()
+        .ok_or("'rule' is not an array of tables")?;
+
+    let mut added = 0;
+    for rule in rules.iter().filter(|r| !taken.contains(&r.category)) {
+        let mut patterns = Array::new();
+        for pattern in &rule.patterns {
+            if pattern.kind == PatternKind::Keyword && pattern.target == Target::Filename {
+                patterns.push(pattern.pattern.as_str());
+                continue;
+            }
+            let mut table = InlineTable::new();
+            table.insert("pattern", pattern.pattern.as_str().into());
+            if pattern.kind != PatternKind::Keyword {
+                table.insert("kind", pattern.kind.name().into());
+            }
+            if pattern.target != Target::Filename {
+                table.insert("target", pattern.target.name().into());
+            }
+            patterns.push(table);
+        }
+        let mut table = Table::new();
+        table.decor_mut().set_prefix(format!("\n# {}\n", rule.note));
+        table.insert("category", value(rule.category.as_str()));
+        table.insert("patterns", value(patterns));
+        tables.push(table);
+        added += 1;
+    }
+    Ok((document.to_string(), added))
+}
+
+/// Merges `rules` into the config at `config_path` with [`merge_into_config`],
+/// creating the file if needed. Returns the number of rules added.
+pub fn write_rules(config_path: &Path, rules: &[ProposedRule]) -> Result<usize, String> {
+    let existing = match fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Failed to read {:?}: {}", config_path, e)),
+    };
+    let (content, added) = merge_into_config(&existing, rules)
+        .map_err(|e| format!("Failed to parse {:?}: {}", config_path, e))?;
+    // never write something `organize` would refuse to load
+    parse_config(config_path, &content).map_err(|e| e.to_string())?;
+    fs::write(config_path, content)
+        .map_err(|e| format!("Failed to write {:?}: {}", config_path, e))?;
+    Ok(added)
+}
+
+/// Proposes rules from the samples in `source` and merges them into the
+/// config at `config_path`, creating it if needed. Rules for categories the
+/// config already has are left alone. Returns the number of rules added.
+pub fn emit_config(source: &Path, config_path: &Path) -> Result<usize, String> {
+    write_rules(config_path, &propose_rules(&find_pattern_groups(source)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn group(prefix: &str, count: usize) -> PatternGroup {
+        PatternGroup {
+            prefix: prefix.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_clean_prefix() {
+        assert_eq!(clean_prefix("Kick_0"), "kick");
+        assert_eq!(clean_prefix("Snare Hard "), "snare hard");
+        assert_eq!(clean_prefix("808 "), "808");
+        assert_eq!(clean_prefix("Vox (1"), "vox");
+    }
+
+    fn regex(pattern: &str) -> Pattern {
+        Pattern {
+            pattern: pattern.to_string(),
+            kind: PatternKind::Regex,
+            target: Target::Filename,
+        }
+    }
+
+    #[test]
+    fn test_propose_rules() {
+        let folders = vec![
+            (
+                "a".to_string(),
+                vec![
+                    group("Kick_0", 9),
+                    group("Snare Hard ", 3),
+                    group("Crash Ride Long", 1),
+                ],
+            ),
+            (
+                "b".to_string(),
+                vec![group("KICK ", 2), group("kick_", 4), group("Fx.", 2)],
+            ),
+        ];
+        assert_eq!(
+            propose_rules(&folders),
+            vec![
+                ProposedRule {
+                    category: "kick".to_string(),
+                    patterns: vec![regex("^kick")],
+                    note: "discovered: 15 files".to_string(),
+                },
+                ProposedRule {
+                    category: "snare_hard".to_string(),
+                    patterns: vec![regex("^snare hard")],
+                    note: "discovered: 3 files".to_string(),
+                },
+                ProposedRule {
+                    category: "fx".to_string(),
+                    patterns: vec![regex("^fx")],
+                    note: "discovered: 2 files".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_into_config_keeps_hand_written_rules() {
+        let existing =
+            "# my rules\n[[rule]]\ncategory = \"kick\"\npatterns = [\"bd\"] # bass drums\n";
+        let rules = vec![
+            ProposedRule {
+                category: "kick".to_string(),
+                patterns: vec![regex("^kick")],
+                note: "discovered: 4 files".to_string(),
+            },
+            ProposedRule {
+                category: "fx".to_string(),
+                patterns: vec![
+                    regex("^fx\\."),
+                    Pattern::new("sfx"),
+                    Pattern {
+                        pattern: "fx".to_string(),
+                        kind: PatternKind::Token,
+                        target: Target::Folder,
+                    },
+                ],
+                note: "discovered: 2 files".to_string(),
+            },
+        ];
+        let (content, added) = merge_into_config(existing, &rules).unwrap();
+
+        assert_eq!(added, 1);
+        assert_eq!(
+            content,
+            "# my rules\n[[rule]]\ncategory = \"kick\"\npatterns = [\"bd\"] # bass drums\n\n\
+             # discovered: 2 files\n[[rule]]\ncategory = \"fx\"\n\
+             patterns = [{ pattern = '^fx\\.', kind = \"regex\" }, \"sfx\", \
+             { pattern = \"fx\", kind = \"token\", target = \"folder\" }]\n"
+        );
+        let config = parse_config(Path::new("config.toml"), &content).unwrap();
+        assert_eq!(config.rules[1].patterns, rules[1].patterns);
+    }
+
+    #[test]
+    fn test_emit_config_writes_loadable_file() {
+        let source = tempdir().unwrap();
+        for name in [
+            "Kick_01.wav",
+            "Kick_02.wav",
+            "Kick_03.wav",
+            "Pad Warm 1.wav",
+            "Pad Warm 2.wav",
+        ] {
+            fs::File::create(source.path().join(name)).unwrap();
+        }
+        let dest = tempdir().unwrap();
+        let config_path = dest.path().join("config.toml");
+
+        assert_eq!(emit_config(source.path(), &config_path).unwrap(), 2);
+        // running again adds nothing
+        assert_eq!(emit_config(source.path(), &config_path).unwrap(), 0);
+
+        let config = crate::config::load_config(&config_path, None).unwrap();
+        let categories: Vec<_> = config.rules.iter().map(|r| r.category.as_str()).collect();
+        assert_eq!(categories, ["kick", "pad_warm"]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::categorizer::Explanation;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Writes a report of how `sample` was categorized: every rule that matched,
+/// in the order they were tried, what the content classifier heard if it was
+/// consulted, and the final decision.
+pub fn print_explanation<W: Write>(
+    writer: &mut W,
+    sample: &Path,
+    explanation: &Explanation,
+) -> io::Result<()> {
+    writeln!(writer, "{}", sample.display())?;
+    if explanation.matches.is_empty() {
+        writeln!(writer, "  no rule matched")?;
+    }
+    for (i, m) in explanation.matches.iter().enumerate() {
+        let patterns = m
+            .patterns
+            .iter()
+            .map(|p| format!("{:?}", p))
+            .chain(m.fuzzy.iter().map(|f| format!("fuzzy {}", f)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let outcome = match (&m.skipped, explanation.global_exclude) {
+            (Some(reason), _) => format!("skipped: {}", reason),
+            (None, Some(pattern)) => format!("chosen, then excluded by {:?}", pattern),
+            (None, None) => "chosen".to_string(),
+        };
+        writeln!(
+            writer,
+            "  {}. {} [{}] {}",
+            i + 1,
+            m.category,
+            patterns,
+            outcome
+        )?;
+    }
+
+    if let Some(content) = &explanation.content {
+        writeln!(
+            writer,
+            "  sounds like: {} (confidence {:.2})",
+            content.class, content.confidence
+        )?;
+    }
+
+    match (explanation.decision, explanation.global_exclude) {
+        (Some(category), _) => writeln!(writer, "  => {}", category.name),
+        (None, Some(pattern)) => {
+            writeln!(writer, "  => uncategorized (global exclude {:?})", pattern)
+        }
+        (None, None) if explanation.content.is_some() => {
+            writeln!(writer, "  => uncategorized (confidence too low)")
+        }
+        (None, None) => writeln!(writer, "  => uncategorized"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::categorizer::Categorizer;
+    use crate::config::parse_config;
+
+    fn explain(config: &str, sample: &str) -> String {
+        let config = parse_config(Path::new("config.toml"), config).unwrap();
+        let categorizer = Categorizer::new(&config).unwrap();
+        let sample = Path::new(sample);
+        let explanation = categorizer.explain(sample, Path::new("")).unwrap();
+        let mut out = Vec::new();
+        print_explanation(&mut out, sample, &explanation).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_explain_lists_skipped_rules() {
+        let config = "exclude = [\"demo\"]\n\n\
+                      [[rule]]\ncategory = \"drums\"\npatterns = [\"drum\"]\n\n\
+                      [[rule.child]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\nexclude = [\"loop\"]\n\n\
+                      [[rule]]\ncategory = \"loops\"\npatterns = [\"loop\", \"drum\"]\n";
+
+        assert_eq!(
+            explain(config, "drum kick loop.wav"),
+            "drum kick loop.wav\n\
+             \x20 1. drums/kicks [\"kick\"] skipped: excluded by \"loop\"\n\
+             \x20 2. drums [\"drum\"] chosen\n\
+             \x20 3. loops [\"loop\", \"drum\"] skipped: an earlier rule already matched\n\
+             \x20 => drums\n"
+        );
+        assert_eq!(
+            explain(config, "kick.wav"),
+            "kick.wav\n\
+             \x20 1. drums/kicks [\"kick\"] skipped: parent category 'drums' did not match\n\
+             \x20 => uncategorized\n"
+        );
+        assert_eq!(
+            explain(config, "demo loop.wav"),
+            "demo loop.wav\n\
+             \x20 1. loops [\"loop\"] chosen, then excluded by \"demo\"\n\
+             \x20 => uncategorized (global exclude \"demo\")\n"
+        );
+        assert_eq!(
+            explain(config, "pad.wav"),
+            "pad.wav\n  no rule matched\n  => uncategorized\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_marks_a_globally_excluded_winner() {
+        let config = "exclude = [{ pattern = \"demos\", target = \"folder\" }]\n\n\
+                      [[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\nexclude = [\"808\"]\n\n\
+                      [[rule]]\ncategory = \"bass\"\npatterns = [\"808\"]\n";
+
+        assert_eq!(
+            explain(config, "demos/kick 808.wav"),
+            "demos/kick 808.wav\n\
+             \x20 1. kicks [\"kick\"] skipped: excluded by \"808\"\n\
+             \x20 2. bass [\"808\"] chosen, then excluded by \"demos\"\n\
+             \x20 => uncategorized (global exclude \"demos\")\n"
+        );
+        assert!(explain(config, "kicks/kick 808.wav").contains("bass [\"808\"] chosen\n"));
+    }
+
+    #[test]
+    fn test_explain_shows_fuzzy_scores() {
+        let config = "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\nfuzzy = 0.85\n\n\
+                      [[rule]]\ncategory = \"snares\"\npatterns = [\"snare\"]\nfuzzy = 0.85\n";
+
+        assert_eq!(
+            explain(config, "kik 01.wav"),
+            "kik 01.wav\n\
+             \x20 1. kicks [fuzzy \"kick\" ~ \"kik\" (0.93)] chosen\n\
+             \x20 => kicks\n"
+        );
+        assert_eq!(
+            explain(config, "snare kik.wav"),
+            "snare kik.wav\n\
+             \x20 1. kicks [fuzzy \"kick\" ~ \"kik\" (0.93)] skipped: only a fuzzy match, another rule matched exactly\n\
+             \x20 2. snares [\"snare\"] chosen\n\
+             \x20 => snares\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_shows_content_classification() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = dir.path().join("001.wav");
+        let tone: Vec<f32> = (0..88200)
+            .map(|i| (i as f32 * 0.0627).sin() * 0.8)
+            .collect();
+        crate::audio_info::write_test_signal(&sample, 44100, &tone);
+        let sample = sample.to_str().unwrap();
+
+        let config = "[[rule]]\ncategory = \"kicks\"\npatterns = [\"kick\"]\n\n[classifier]\n";
+        assert_eq!(
+            explain(config, sample),
+            format!(
+                "{}\n  no rule matched\n  sounds like: tonal (confidence 0.98)\n  => tonal\n",
+                sample
+            )
+        );
+        let strict = "[classifier]\nmin_confidence = 0.99\n";
+        assert!(explain(strict, sample).ends_with(
+            "  sounds like: tonal (confidence 0.98)\n  => uncategorized (confidence too low)\n"
+        ));
+        assert!(explain("exclude = [\"001\"]\n[classifier]\n", sample)
+            .ends_with("  => uncategorized (global exclude \"001\")\n"));
+    }
+}
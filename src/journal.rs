@@ -0,0 +1,428 @@
+use crate::copier::{self, Mode};
+use crate::utils::file_checksum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Record of the files an `organize` or `apply` run created in the
+/// destination, so `undo` can reverse the run.
+#[derive(Debug, Default, PartialEq)]
+pub struct Journal {
+    /// In the order the files were created.
+    pub entries: Vec<Entry>,
+    /// Folders the run created.
+    pub folders: Vec<PathBuf>,
+}
+
+/// One file created by a run. Paths are absolute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// The sample the file came from.
+    pub source: PathBuf,
+    pub mode: Mode,
+    /// [`file_checksum`] of `path` right after the run created it.
+    pub checksum: String,
+    /// Where `path` pointed right after the run created it, for symlinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+    /// Set if the file replaced an existing one, which can't be brought back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replaced: bool,
+}
+
+/// What [`undo`] did.
+#[derive(Debug, Default, PartialEq)]
+pub struct UndoSummary {
+    /// Files removed or moved back to their source.
+    pub undone: usize,
+    /// Files left in place, and why.
+    pub kept: Vec<(PathBuf, String)>,
+}
+
+/// One line of a journal file.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+enum Record {
+    Folder(PathBuf),
+    File(Entry),
+}
+
+/// Appends to a journal file while a run is going, one JSON line per created
+/// file or folder, so even an interrupted run can be undone.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    state: Mutex<RecorderState>,
+}
+
+#[derive(Debug)]
+struct RecorderState {
+    file: File,
+    /// Recorded folders; several files may report creating the same one.
+    folders: HashSet<PathBuf>,
+}
+
+/// Creates the folder a journal at `path` goes in. Done apart from opening
+/// the journal, whose `AlreadyExists` means the name is taken.
+fn create_parent(path: &Path) -> Result<(), String> {
+    match path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create journal folder {:?}: {}", dir, e)),
+        None => Ok(()),
+    }
+}
+
+impl Recorder {
+    /// Starts a new journal at `path`, failing if a file is already there.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        create_parent(path)?;
+        Self::open(path).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => format!("Journal {:?} already exists", path),
+            _ => format!("Failed to create journal {:?}: {}", path, e),
+        })
+    }
+
+    /// Starts a new journal at `path`, or at `name-2.ext`, `name-3.ext`, ...
+    /// if that's taken.
+    pub fn create_unique(path: &Path) -> Result<Self, String> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        create_parent(path)?;
+        let candidates = (2..).map(|n| path.with_file_name(format!("{}-{}{}", stem, n, ext)));
+        for candidate in std::iter::once(path.to_path_buf()).chain(candidates) {
+            match Self::open(&candidate) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => {
+                    return result
+                        .map_err(|e| format!("Failed to create journal {:?}: {}", candidate, e))
+                }
+            }
+        }
+        unreachable!("some suffix is free")
+    }
+
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            state: Mutex::new(RecorderState {
+                file,
+                folders: HashSet::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records a folder the run created, unless it already was.
+    pub fn folder(&self, folder: &Path) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.folders.insert(folder.to_path_buf()) {
+            return Ok(());
+        }
+        Self::append(&mut state.file, &Record::Folder(folder.to_path_buf())).map_err(|e| {
+            format!(
+                "Failed to record {:?} in journal {:?}: {}",
+                folder, self.path, e
+            )
+        })
+    }
+
+    /// Records a file the run created.
+    pub fn file(&self, entry: Entry) -> Result<(), String> {
+        let path = entry.path.clone();
+        let mut state = self.state.lock().unwrap();
+        Self::append(&mut state.file, &Record::File(entry)).map_err(|e| {
+            format!(
+                "Failed to record {:?} in journal {:?}: {}",
+                path, self.path, e
+            )
+        })
+    }
+
+    fn append(file: &mut File, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        // one write per line, so an interrupted run leaves whole lines
+        file.write_all(line.as_bytes())
+    }
+}
+
+pub fn read_journal(path: &Path) -> Result<Journal, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read journal {:?}: {}", path, e))?;
+    let mut journal = Journal::default();
+    let lines: Vec<&str> = content.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(Record::Folder(folder)) => journal.folders.push(folder),
+            Ok(Record::File(entry)) => journal.entries.push(entry),
+            // the last line of a run that was cut off mid-write
+            Err(_) if i + 1 == lines.len() && !content.ends_with('\n') => {}
+            Err(e) => return Err(format!("Invalid journal {:?}, line {}: {}", path, i + 1, e)),
+        }
+    }
+    Ok(journal)
+}
+
+/// Reverses the run recorded in `journal`, newest file first: copies and
+/// links are deleted and moved samples are moved back to their source.
+///
+/// Files whose contents changed since the run are left alone, as are files
+/// that replaced an earlier one. Folders the run created are removed if
+/// they end up empty.
+pub fn undo(journal: &Journal) -> UndoSummary {
+    let mut summary = UndoSummary::default();
+    for entry in journal.entries.iter().rev() {
+        match undo_entry(entry) {
+            Ok(()) => summary.undone += 1,
+            Err(reason) => summary.kept.push((entry.path.clone(), reason)),
+        }
+    }
+    // innermost first; fails, as it should, for folders that still hold files
+    let mut folders: Vec<&PathBuf> = journal.folders.iter().collect();
+    folders.sort_by_key(|folder| std::cmp::Reverse(folder.components().count()));
+    for folder in folders {
+        let _ = fs::remove_dir(folder);
+    }
+    summary
+}
+
+fn undo_entry(entry: &Entry) -> Result<(), String> {
+    if entry.replaced {
+        return Err("it replaced an earlier file, which can't be restored".to_string());
+    }
+    if !entry.path.exists() && !entry.path.is_symlink() {
+        return Err("it no longer exists".to_string());
+    }
+    if entry.mode == Mode::Symlink {
+        // the link's contents are the sample's, which may have changed or
+        // gone away without the link changing
+        let link = entry.link.as_ref().unwrap_or(&entry.source);
+        match fs::read_link(&entry.path) {
+            Ok(target) if target == *link => {}
+            Ok(_) => return Err("it points elsewhere since the run".to_string()),
+            Err(_) => return Err("it's no longer a link".to_string()),
+        }
+    } else {
+        match file_checksum(&entry.path) {
+            Ok(checksum) if checksum == entry.checksum => {}
+            Ok(_) => return Err("its contents changed since the run".to_string()),
+            Err(e) => return Err(format!("it can't be read: {}", e)),
+        }
+    }
+
+    if entry.mode != Mode::Move {
+        return fs::remove_file(&entry.path).map_err(|e| format!("it can't be removed: {}", e));
+    }
+    if entry.source.exists() || entry.source.is_symlink() {
+        return Err(format!("its source {:?} exists again", entry.source));
+    }
+    copier::carry_out(&entry.path, &entry.source, None, Mode::Move)
+        .map_err(|e| format!("it can't be moved back: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(path: &Path, source: &Path, mode: Mode) -> Entry {
+        Entry {
+            path: path.to_path_buf(),
+            source: source.to_path_buf(),
+            mode,
+            checksum: file_checksum(path).unwrap(),
+            link: fs::read_link(path).ok(),
+            replaced: false,
+        }
+    }
+
+    #[test]
+    fn test_undo_removes_copies_and_moves_back() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("in");
+        let kicks = dir.path().join("out").join("kicks");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&kicks).unwrap();
+        fs::write(source.join("copied.wav"), "a").unwrap();
+        fs::write(kicks.join("copied.wav"), "a").unwrap();
+        fs::write(kicks.join("moved.wav"), "b").unwrap();
+        fs::write(kicks.join("edited.wav"), "c").unwrap();
+
+        let journal = Journal {
+            entries: vec![
+                entry(
+                    &kicks.join("copied.wav"),
+                    &source.join("copied.wav"),
+                    Mode::Copy,
+                ),
+                entry(
+                    &kicks.join("moved.wav"),
+                    &source.join("moved.wav"),
+                    Mode::Move,
+                ),
+                entry(
+                    &kicks.join("edited.wav"),
+                    &source.join("edited.wav"),
+                    Mode::Copy,
+                ),
+            ],
+            folders: vec![dir.path().join("out"), kicks.clone()],
+        };
+        fs::write(kicks.join("edited.wav"), "changed").unwrap();
+
+        let summary = undo(&journal);
+        assert_eq!(summary.undone, 2);
+        assert_eq!(
+            summary.kept,
+            [(
+                kicks.join("edited.wav"),
+                "its contents changed since the run".to_string()
+            )]
+        );
+        assert!(!kicks.join("copied.wav").exists());
+        assert_eq!(fs::read_to_string(source.join("moved.wav")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(source.join("copied.wav")).unwrap(), "a");
+        // the folder still holds the edited file
+        assert!(kicks.is_dir());
+    }
+
+    #[test]
+    fn test_undo_keeps_replacements_and_cleans_up_folders() {
+        let dir = tempdir().unwrap();
+        let kicks = dir.path().join("out").join("kicks");
+        fs::create_dir_all(&kicks).unwrap();
+        fs::write(kicks.join("new.wav"), "a").unwrap();
+        fs::write(kicks.join("replaced.wav"), "b").unwrap();
+
+        let mut replaced = entry(
+            &kicks.join("replaced.wav"),
+            Path::new("in/replaced.wav"),
+            Mode::Copy,
+        );
+        replaced.replaced = true;
+        let journal = Journal {
+            entries: vec![
+                entry(
+                    &kicks.join("new.wav"),
+                    Path::new("in/new.wav"),
+                    Mode::Hardlink,
+                ),
+                replaced,
+            ],
+            folders: vec![kicks.clone()],
+        };
+        let path = dir.path().join("journal.jsonl");
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.folder(&kicks).unwrap();
+        recorder.folder(&kicks).unwrap();
+        for entry in &journal.entries {
+            recorder.file(entry.clone()).unwrap();
+        }
+        assert_eq!(read_journal(&path).unwrap(), journal);
+
+        let summary = undo(&journal);
+        assert_eq!(summary.undone, 1);
+        assert_eq!(summary.kept.len(), 1);
+        assert!(kicks.join("replaced.wav").exists());
+
+        fs::remove_file(kicks.join("replaced.wav")).unwrap();
+        let summary = undo(&journal);
+        assert_eq!(summary.undone, 0);
+        assert_eq!(summary.kept[1].1, "it no longer exists");
+        assert!(!kicks.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_removes_links_by_target() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("in");
+        let kicks = dir.path().join("out").join("kicks");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&kicks).unwrap();
+        for name in ["dangling.wav", "edited.wav", "repointed.wav", "other.wav"] {
+            fs::write(source.join(name), name).unwrap();
+        }
+        for name in ["dangling.wav", "edited.wav", "repointed.wav"] {
+            std::os::unix::fs::symlink(source.join(name), kicks.join(name)).unwrap();
+        }
+
+        let journal = Journal {
+            entries: ["dangling.wav", "edited.wav", "repointed.wav"]
+                .map(|name| entry(&kicks.join(name), &source.join(name), Mode::Symlink))
+                .to_vec(),
+            folders: vec![],
+        };
+        fs::remove_file(source.join("dangling.wav")).unwrap();
+        fs::write(source.join("edited.wav"), "changed").unwrap();
+        fs::remove_file(kicks.join("repointed.wav")).unwrap();
+        std::os::unix::fs::symlink(source.join("other.wav"), kicks.join("repointed.wav")).unwrap();
+
+        let summary = undo(&journal);
+        assert_eq!(summary.undone, 2);
+        assert_eq!(
+            summary.kept,
+            [(
+                kicks.join("repointed.wav"),
+                "it points elsewhere since the run".to_string()
+            )]
+        );
+        assert!(!kicks.join("dangling.wav").is_symlink());
+        assert!(!kicks.join("edited.wav").is_symlink());
+        assert_eq!(
+            fs::read_to_string(source.join("edited.wav")).unwrap(),
+            "changed"
+        );
+    }
+
+    #[test]
+    fn test_recorder_never_replaces_a_journal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("runs").join("run.jsonl");
+        let first = Recorder::create_unique(&path).unwrap();
+        let second = Recorder::create_unique(&path).unwrap();
+        assert_eq!(first.path(), path);
+        assert_eq!(second.path(), dir.path().join("runs").join("run-2.jsonl"));
+        assert!(Recorder::create(&path)
+            .unwrap_err()
+            .ends_with("already exists"));
+
+        // a file where the folder should be is an error, not a taken name
+        fs::write(dir.path().join("taken"), "").unwrap();
+        let path = dir.path().join("taken").join("run.jsonl");
+        assert!(Recorder::create_unique(&path)
+            .unwrap_err()
+            .starts_with("Failed to create journal folder"));
+    }
+
+    #[test]
+    fn test_read_journal_of_an_interrupted_run() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("run.jsonl");
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.folder(Path::new("/out/kicks")).unwrap();
+        drop(recorder);
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str("{\"file\": {\"path\": \"/out/ki");
+        fs::write(&path, &content).unwrap();
+
+        let journal = read_journal(&path).unwrap();
+        assert_eq!(journal.folders, [PathBuf::from("/out/kicks")]);
+        assert!(journal.entries.is_empty());
+
+        fs::write(&path, content + "\n").unwrap();
+        assert!(read_journal(&path).unwrap_err().contains("line 2"));
+    }
+}
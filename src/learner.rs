@@ -0,0 +1,283 @@
+use crate::config::{Pattern, PatternKind, Target};
+use crate::discoverer::{clean_prefix, find_pattern_groups, ProposedRule};
+use crate::sample_finder::find_samples;
+use crate::utils::tokenize;
+use natord::compare;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most patterns proposed for one category.
+const MAX_PATTERNS: usize = 8;
+
+/// A category learned from one top-level folder of an organized library.
+#[derive(Debug, PartialEq)]
+pub struct LearnedRule {
+    pub category: String,
+    /// Token patterns, each one or more space-separated tokens.
+    pub tokens: Vec<String>,
+    /// Share of the samples matched by the rule that are in this folder.
+    pub precision: f64,
+    /// Share of the folder's samples matched by the rule.
+    pub recall: f64,
+    /// Samples in the folder matched by the rule.
+    pub matched: usize,
+    /// Samples in the folder.
+    pub total: usize,
+}
+
+impl LearnedRule {
+    pub fn to_proposed(&self) -> ProposedRule {
+        ProposedRule {
+            category: self.category.clone(),
+            patterns: self
+                .tokens
+                .iter()
+                .map(|t| Pattern {
+                    pattern: t.clone(),
+                    kind: PatternKind::Token,
+                    target: Target::Stem,
+                })
+                .collect(),
+            note: format!(
+                "learned: precision {:.2}, recall {:.2} ({} of {} samples)",
+                self.precision, self.recall, self.matched, self.total
+            ),
+        }
+    }
+}
+
+/// One labelled sample: its folder and the tokens of its file name.
+struct Example {
+    label: usize,
+    tokens: Vec<String>,
+}
+
+impl Example {
+    fn contains(&self, phrase: &[String]) -> bool {
+        self.tokens.windows(phrase.len()).any(|w| w == phrase)
+    }
+}
+
+/// Learns one rule per top-level folder of `library`, treating each folder
+/// as a category and the samples below it as its examples.
+///
+/// Candidate patterns are the tokens of the sample names plus the multi-word
+/// prefixes found by the discoverer. For each folder, candidates that match at
+/// least two of its samples with at least `min_precision` are picked greedily
+/// by how many not yet covered samples they add. Folders without any such
+/// candidate are returned by name in the second list.
+pub fn learn(
+    library: &Path,
+    min_precision: f64,
+) -> Result<(Vec<LearnedRule>, Vec<String>), String> {
+    let entries =
+        fs::read_dir(library).map_err(|e| format!("Failed to read {:?}: {}", library, e))?;
+    let mut labels: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| (e.file_name().to_string_lossy().into_owned(), e.path()))
+        .collect();
+    labels.sort_by(|a, b| compare(&a.0, &b.0));
+
+    let mut examples = Vec::new();
+    let mut candidates: Vec<HashSet<Vec<String>>> = Vec::new();
+    for (label, (_, dir)) in labels.iter().enumerate() {
+        let mut phrases = HashSet::new();
+        for sample in find_samples(dir) {
+            let stem = sample.file_stem().unwrap_or_default().to_string_lossy();
+            let tokens = tokenize(&stem);
+            phrases.extend(
+                tokens
+                    .iter()
+                    .filter(|t| is_useful(t))
+                    .map(|t| vec![t.clone()]),
+            );
+            examples.push(Example { label, tokens });
+        }
+        for group in find_pattern_groups(dir)
+            .iter()
+            .flat_map(|(_, groups)| groups)
+        {
+            let phrase = tokenize(&clean_prefix(&group.prefix));
+            if group.count >= 2 && phrase.len() > 1 {
+                phrases.insert(phrase);
+            }
+        }
+        candidates.push(phrases);
+    }
+
+    let mut rules = Vec::new();
+    let mut unlearned = Vec::new();
+    for (label, (name, _)) in labels.iter().enumerate() {
+        let total = examples.iter().filter(|e| e.label == label).count();
+        let phrases: Vec<&Vec<String>> = candidates[label]
+            .iter()
+            .filter(|phrase| {
+                let (hits, precision) = score(&examples, label, &[phrase]);
+                hits >= 2 && precision >= min_precision
+            })
+            .collect();
+
+        let mut chosen: Vec<&Vec<String>> = Vec::new();
+        let mut covered = vec![false; examples.len()];
+        while chosen.len() < MAX_PATTERNS {
+            let best = phrases
+                .iter()
+                .map(|&phrase| {
+                    let gain = examples
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, e)| e.label == label && !covered[*i] && e.contains(phrase))
+                        .count();
+                    (gain, phrase)
+                })
+                .filter(|(gain, _)| *gain > 0)
+                // ties go to the alphabetically first phrase, so runs agree
+                .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)));
+            let Some((_, phrase)) = best else {
+                break;
+            };
+            for (i, e) in examples.iter().enumerate() {
+                covered[i] |= e.contains(phrase);
+            }
+            chosen.push(phrase);
+        }
+
+        if chosen.is_empty() {
+            unlearned.push(name.clone());
+            continue;
+        }
+        let (matched, precision) = score(&examples, label, &chosen);
+        rules.push(LearnedRule {
+            category: name.clone(),
+            tokens: chosen.iter().map(|p| p.join(" ")).collect(),
+            precision,
+            recall: matched as f64 / total as f64,
+            matched,
+            total,
+        });
+    }
+    Ok((rules, unlearned))
+}
+
+/// Samples of `label` matched by any of `phrases`, and the share of all
+/// matched samples that belong to `label`.
+fn score(examples: &[Example], label: usize, phrases: &[&Vec<String>]) -> (usize, f64) {
+    let matched: Vec<&Example> = examples
+        .iter()
+        .filter(|e| phrases.iter().any(|p| e.contains(p)))
+        .collect();
+    let hits = matched.iter().filter(|e| e.label == label).count();
+    let precision = if matched.is_empty() {
+        0.0
+    } else {
+        hits as f64 / matched.len() as f64
+    };
+    (hits, precision)
+}
+
+/// Numbers and single letters say nothing about a category.
+fn is_useful(token: &str) -> bool {
+    token.chars().count() > 1 && !token.chars().all(|c| c.is_numeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::categorizer::Categorizer;
+    use crate::config::Config;
+    use tempfile::tempdir;
+
+    fn create_library(root: &Path) {
+        let files = [
+            (
+                "Kicks",
+                vec![
+                    "BD_01",
+                    "BD_02",
+                    "Kick Hard",
+                    "Kick Soft",
+                    "Kick Room",
+                    "odd one",
+                ],
+            ),
+            (
+                "Snares",
+                vec![
+                    "SD_01",
+                    "SD_02",
+                    "Snare Tight",
+                    "Snare Room",
+                    "Rim Shot 1",
+                    "Rim Shot 2",
+                ],
+            ),
+            (
+                "Hats",
+                vec!["OpenHat 1", "OpenHat 2", "ClosedHat 1", "ClosedHat 2"],
+            ),
+            ("Misc", vec!["thing"]),
+        ];
+        for (folder, names) in files {
+            let dir = root.join(folder).join("sub");
+            fs::create_dir_all(&dir).unwrap();
+            for name in names {
+                fs::File::create(dir.join(format!("{}.wav", name))).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_learn_rules_per_folder() {
+        let library = tempdir().unwrap();
+        create_library(library.path());
+
+        let (rules, unlearned) = learn(library.path(), 0.9).unwrap();
+        assert_eq!(unlearned, ["Misc"]);
+
+        let summary: Vec<_> = rules
+            .iter()
+            .map(|r| (r.category.as_str(), r.tokens.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Hats", vec!["hat".to_string()]),
+                ("Kicks", vec!["kick".to_string(), "bd".to_string()]),
+                (
+                    "Snares",
+                    vec!["rim".to_string(), "sd".to_string(), "snare".to_string()]
+                ),
+            ]
+        );
+        // "room" appears in two folders and is never chosen; "odd one" is missed
+        assert_eq!((rules[1].matched, rules[1].total), (5, 6));
+        assert!((rules[1].recall - 5.0 / 6.0).abs() < 1e-9);
+        assert_eq!(rules[1].precision, 1.0);
+    }
+
+    #[test]
+    fn test_learned_rules_categorize_library() {
+        let library = tempdir().unwrap();
+        create_library(library.path());
+        let (rules, _) = learn(library.path(), 0.9).unwrap();
+
+        let (content, _) = crate::discoverer::merge_into_config(
+            "",
+            &rules
+                .iter()
+                .map(LearnedRule::to_proposed)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        assert!(content.contains("# learned: precision 1.00, recall 0.83 (5 of 6 samples)\n"));
+        let config: Config =
+            crate::config::parse_config(Path::new("learned.toml"), &content).unwrap();
+        let categorizer = Categorizer::new(&config).unwrap();
+        let category = categorizer
+            .categorize(Path::new("Snare Fat.wav"), Path::new(""))
+            .unwrap();
+        assert_eq!(category.name, "Snares");
+    }
+}
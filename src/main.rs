@@ -1,14 +1,27 @@
-mod config;
-mod sample_finder;
+mod audio_info;
 mod categorizer;
+mod classifier;
+mod config;
+mod config_checker;
 mod copier;
-mod tree_printer;
 mod discoverer;
+mod explainer;
+mod journal;
+mod learner;
+mod organizer;
+mod plan;
+mod progress;
+mod sample_finder;
+mod template;
+mod tree_printer;
 mod utils;
 
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
-use std::io;
+use std::io::{self, IsTerminal};
+use std::path::{Component, Path, PathBuf};
+
+/// The config file used when `--config` isn't given.
+const DEFAULT_CONFIG: &str = "config.toml";
 
 #[derive(Parser)]
 #[command(name = "Sample Organizer", version)]
@@ -24,8 +37,63 @@ enum Command {
         source: PathBuf,
         #[arg(short, long)]
         dest: PathBuf,
-        #[arg(short, long, default_value = "config.toml")]
-        config: PathBuf,
+        /// The config file, or `builtin` for the built-in categories
+        /// [default: config.toml, or the built-in categories if there is none]
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Merge the named `[profile.<name>]` section of the config on top
+        #[arg(long)]
+        profile: Option<String>,
+        /// Copy samples that match no category into this folder of the
+        /// destination, keeping their path below the source
+        #[arg(long, value_name = "FOLDER", num_args = 0..=1, default_missing_value = "_uncategorized")]
+        uncategorized: Option<PathBuf>,
+        /// Write the paths of samples that match no category to this file
+        #[arg(long, value_name = "FILE")]
+        uncategorized_report: Option<PathBuf>,
+        /// How samples are placed in the destination; links that can't be
+        /// created fall back to copies
+        #[arg(long, value_enum, default_value_t = copier::Mode::Copy)]
+        mode: copier::Mode,
+        /// What to do when a file with the same name is already at a
+        /// sample's destination
+        #[arg(long, value_enum, default_value_t = copier::OnConflict::Skip)]
+        on_conflict: copier::OnConflict,
+        /// Place every sample, even if its category already has one with
+        /// identical audio
+        #[arg(long)]
+        keep_duplicates: bool,
+        /// Write every planned operation to this JSON file instead of
+        /// touching the destination; carry it out later with `apply`
+        #[arg(long, value_name = "FILE")]
+        plan: Option<PathBuf>,
+        /// Record the files the run creates in this JSON Lines file, for `undo`
+        /// [default: <DEST>.journals/<TIMESTAMP>.jsonl, next to the destination]
+        #[arg(long, value_name = "FILE", conflicts_with = "plan")]
+        journal: Option<PathBuf>,
+        /// Number of files read and written at once [default: number of CPUs]
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+
+    /// Carry out a plan written by `organize --plan`
+    Apply {
+        /// The plan file, possibly edited by hand
+        plan: PathBuf,
+        /// Record the files the run creates in this JSON Lines file, for `undo`
+        /// [default: the plan's path with a `.journal.jsonl` extension]
+        #[arg(long, value_name = "FILE")]
+        journal: Option<PathBuf>,
+        /// Number of files written at once [default: number of CPUs]
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+
+    /// Reverse an `organize` or `apply` run, leaving alone files that
+    /// changed since
+    Undo {
+        /// The journal the run wrote
+        journal: PathBuf,
     },
 
     Tree {
@@ -43,6 +111,62 @@ enum Command {
         #[arg(long)]
         run_discover: bool,
     },
+
+    /// Find filename prefixes shared by samples in the same folder
+    Discover {
+        #[arg(short, long)]
+        source: PathBuf,
+        /// Add a rule for every discovered category to this config file,
+        /// keeping existing rules
+        #[arg(long, value_name = "CONFIG")]
+        emit_config: Option<PathBuf>,
+    },
+
+    /// Propose rules from a library that is already sorted into folders
+    Learn {
+        /// Library whose top-level folders are the categories to learn
+        #[arg(long)]
+        from: PathBuf,
+        /// Add the learned rules to this config file instead of printing them
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Only use tokens at least this share of whose matches are in the
+        /// category's folder
+        #[arg(long, default_value_t = 0.9)]
+        min_precision: f64,
+    },
+
+    /// Show which rules match the given samples and which category wins
+    Explain {
+        /// Samples to explain
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Source directory that path patterns are matched relative to
+        #[arg(short, long)]
+        source: Option<PathBuf>,
+        /// The config file, or `builtin` for the built-in categories
+        /// [default: config.toml, or the built-in categories if there is none]
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Merge the named `[profile.<name>]` section of the config on top
+        #[arg(long)]
+        profile: Option<String>,
+    },
+
+    /// Inspect configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Validate a config file and report every problem with its location
+    Check {
+        #[arg(short, long, default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
 }
 
 fn main() {
@@ -53,33 +177,166 @@ fn main() {
             source,
             dest,
             config,
+            profile,
+            uncategorized,
+            uncategorized_report,
+            mode,
+            on_conflict,
+            keep_duplicates,
+            plan,
+            journal,
+            jobs,
         } => {
             // TODO
-            let config = match config::load_config(config) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Error loading config: {}", e);
+            set_jobs(*jobs);
+            if let Some(folder) = uncategorized {
+                if !folder
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+                {
+                    eprintln!(
+                        "Error: --uncategorized must be a folder inside the destination, got {:?}",
+                        folder
+                    );
                     return;
                 }
+            }
+            let Some(categorizer) = load_categorizer(config.as_deref(), profile.as_deref()) else {
+                return;
             };
-            let samples = sample_finder::find_samples(source);
-            let mut copied_count = 0;
-            let mut uncategorized_count = 0;
-
-            for sample in &samples {
-                if let Some(category) = categorizer::categorize_sample(sample, &config) {
-                    if let Err(e) = copier::copy_to_dest(sample, dest, &category) {
-                        eprintln!("Error copying file: {}", e);
+            let options = organizer::Options {
+                uncategorized: uncategorized.clone(),
+                mode: *mode,
+                on_conflict: *on_conflict,
+                dedup: !keep_duplicates,
+                progress: io::stderr().is_terminal(),
+            };
+            let summary = match plan {
+                Some(plan_path) => {
+                    // a plan may be applied from another directory
+                    let (Ok(source), Ok(dest)) =
+                        (std::path::absolute(source), std::path::absolute(dest))
+                    else {
+                        eprintln!("Error: could not resolve the source and destination paths");
+                        return;
+                    };
+                    let samples = scan(&source, options.progress);
+                    let (plan, expected) =
+                        organizer::plan(&categorizer, &samples, &source, &dest, &options);
+                    if let Err(e) = plan::write_plan(plan_path, &plan) {
+                        eprintln!("Error: {}", e);
+                        return;
                     }
-                    copied_count += 1;
-                } else {
-                    uncategorized_count += 1;
+                    println!("-");
+                    println!(
+                        "Wrote {} operations to {:?} without changing anything. Run `apply {}` to carry them out.",
+                        plan.operations.len(),
+                        plan_path,
+                        plan_path.display()
+                    );
+                    print_summary(
+                        &expected,
+                        "Would place",
+                        *mode,
+                        uncategorized.as_ref().map(|f| dest.join(f)),
+                    );
+                    expected
+                }
+                None => {
+                    let recorder = match journal {
+                        Some(path) => journal::Recorder::create(path),
+                        None => create_default_journal(dest),
+                    };
+                    let recorder = match recorder {
+                        Ok(recorder) => recorder,
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            return;
+                        }
+                    };
+                    let samples = scan(source, options.progress);
+                    let summary = organizer::organize(
+                        &categorizer,
+                        &samples,
+                        source,
+                        dest,
+                        &options,
+                        Some(&recorder),
+                    );
+                    println!("-");
+                    println!("Organization complete.");
+                    print_summary(
+                        &summary,
+                        "Placed",
+                        *mode,
+                        uncategorized.as_ref().map(|f| dest.join(f)),
+                    );
+                    finish_journal(recorder, &summary.journal);
+                    summary
+                }
+            };
+            if let Some(report) = uncategorized_report {
+                match organizer::write_report(report, &summary.uncategorized) {
+                    Ok(()) => println!("Wrote the uncategorized files to {:?}.", report),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+        }
+
+        Command::Apply {
+            plan: plan_path,
+            journal,
+            jobs,
+        } => {
+            set_jobs(*jobs);
+            let plan = match plan::read_plan(plan_path) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            };
+            let recorder = match journal {
+                Some(path) => journal::Recorder::create(path),
+                None => {
+                    journal::Recorder::create_unique(&plan_path.with_extension("journal.jsonl"))
+                }
+            };
+            let recorder = match recorder {
+                Ok(recorder) => recorder,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            };
+            let summary = organizer::apply(&plan, io::stderr().is_terminal(), Some(&recorder));
+            println!("-");
+            println!("Apply complete.");
+            print_summary(&summary, "Placed", plan.mode, None);
+            finish_journal(recorder, &summary.journal);
+        }
+
+        Command::Undo { journal } => {
+            let journal = match journal::read_journal(journal) {
+                Ok(journal) => journal,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
                 }
+            };
+            let summary = journal::undo(&journal);
+            for (path, reason) in &summary.kept {
+                println!("Kept {:?}: {}", path, reason);
             }
             println!("-");
-            println!("Organization complete.");
-            println!("Copied {} files.", copied_count);
-            println!("{} files were not categorized.", uncategorized_count);
+            println!(
+                "Undid {} of {} files.",
+                summary.undone,
+                journal.entries.len()
+            );
+            if !summary.kept.is_empty() {
+                std::process::exit(1);
+            }
         }
 
         Command::Tree {
@@ -91,20 +348,17 @@ fn main() {
             if *run_discover {
                 discoverer::discover_patterns(&mut std::io::stdout(), source).unwrap();
             } else if *list_categories {
-                let config = match config::load_config(&PathBuf::from("config.toml")) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Error loading config: {}", e);
-                        return;
-                    }
+                let Some(categorizer) = load_categorizer(None, None) else {
+                    return;
                 };
 
                 let samples = sample_finder::find_samples(source);
-                let mut category_counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+                let mut category_counts: std::collections::HashMap<String, usize> =
+                    std::collections::HashMap::new();
 
                 for sample in samples {
-                    if let Some(category) = categorizer::categorize_sample(&sample, &config) {
-                        *category_counts.entry(category).or_insert(0) += 1;
+                    if let Some(category) = categorizer.categorize(&sample, source) {
+                        *category_counts.entry(category.name.clone()).or_insert(0) += 1;
                     }
                 }
 
@@ -121,8 +375,312 @@ fn main() {
                 }
             } else {
                 println!("{}", source.display());
-                tree_printer::print_tree(&mut io::stdout(), source, "".into(), *folders_only).unwrap();
+                tree_printer::print_tree(&mut io::stdout(), source, "".into(), *folders_only)
+                    .unwrap();
+            }
+        }
+
+        Command::Discover {
+            source,
+            emit_config,
+        } => match emit_config {
+            Some(config) => match discoverer::emit_config(source, config) {
+                Ok(added) => println!("Added {} rule(s) to {}.", added, config.display()),
+                Err(e) => {
+                    eprintln!("Error writing config: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => discoverer::discover_patterns(&mut io::stdout(), source).unwrap(),
+        },
+
+        Command::Learn {
+            from,
+            output,
+            min_precision,
+        } => {
+            let (learned, unlearned) = match learner::learn(from, *min_precision) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            for category in &unlearned {
+                eprintln!("No distinguishing tokens found for '{}'.", category);
+            }
+            let rules: Vec<_> = learned
+                .iter()
+                .map(learner::LearnedRule::to_proposed)
+                .collect();
+            match output {
+                Some(path) => match discoverer::write_rules(path, &rules) {
+                    Ok(added) => println!("Added {} rule(s) to {}.", added, path.display()),
+                    Err(e) => {
+                        eprintln!("Error writing config: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                None => print!("{}", discoverer::merge_into_config("", &rules).unwrap().0),
+            }
+        }
+
+        Command::Explain {
+            files,
+            source,
+            config,
+            profile,
+        } => {
+            let Some(categorizer) = load_categorizer(config.as_deref(), profile.as_deref()) else {
+                std::process::exit(1);
+            };
+            let root = source.as_deref().unwrap_or(Path::new(""));
+            for file in files {
+                match categorizer.explain(file, root) {
+                    Some(explanation) => {
+                        explainer::print_explanation(&mut io::stdout(), file, &explanation).unwrap()
+                    }
+                    None => eprintln!("{}: not a file name", file.display()),
+                }
+            }
+        }
+
+        Command::Config {
+            action: ConfigCommand::Check { config },
+        } => {
+            let diagnostics = match config_checker::check_config(config) {
+                Ok(d) => d,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            for d in &diagnostics {
+                println!("{}", d);
+            }
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == config_checker::Severity::Error)
+                .count();
+            let warnings = diagnostics.len() - errors;
+            if diagnostics.is_empty() {
+                println!("{}: OK", config.display());
+            } else {
+                println!(
+                    "{}: {} error(s), {} warning(s)",
+                    config.display(),
+                    errors,
+                    warnings
+                );
+            }
+            if errors > 0 {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Prints the counts and lists of an `organize` or `apply` run; `placed` is
+/// the verb for the transferred samples. `uncategorized` is the folder
+/// uncategorized samples were placed in, if any.
+fn print_summary(
+    summary: &organizer::Summary,
+    placed: &str,
+    mode: copier::Mode,
+    uncategorized: Option<PathBuf>,
+) {
+    println!(
+        "{} {} files (mode: {}).",
+        placed,
+        summary.transferred,
+        mode.name()
+    );
+    match uncategorized {
+        Some(folder) => println!(
+            "{} files were not categorized and go to {:?}.",
+            summary.uncategorized.len(),
+            folder
+        ),
+        None => println!(
+            "{} files were not categorized.",
+            summary.uncategorized.len()
+        ),
+    }
+    if !summary.collisions.is_empty() {
+        println!(
+            "{} files collide with a file at their destination:",
+            summary.collisions.len()
+        );
+        for c in &summary.collisions {
+            println!("  {:?} -> {:?}: {}", c.sample, c.dest, c.resolution);
+        }
+    }
+    if !summary.duplicates.is_empty() {
+        println!(
+            "{} files were left out as duplicates of a sample in the same category:",
+            summary.duplicates.len()
+        );
+        // grouped by the sample placed instead, in the order they were found
+        let mut groups: Vec<(&plan::Duplicate, Vec<&PathBuf>)> = Vec::new();
+        for d in &summary.duplicates {
+            match groups
+                .iter_mut()
+                .find(|(first, _)| first.original == d.original)
+            {
+                Some((_, copies)) => copies.push(&d.sample),
+                None => groups.push((d, vec![&d.sample])),
+            }
+        }
+        for (first, copies) in groups {
+            println!(
+                "  {:?} ({}), also found at:",
+                first.original, first.category
+            );
+            for copy in copies {
+                println!("    {:?}", copy);
+            }
+        }
+    }
+    if !summary.classified.is_empty() {
+        println!(
+            "{} files were sorted by their audio content:",
+            summary.classified.len()
+        );
+        for c in &summary.classified {
+            println!(
+                "  {:?} -> {} ({}, confidence {:.2})",
+                c.sample, c.category, c.classification.class, c.classification.confidence
+            );
+        }
+    }
+}
+
+/// Limits the worker threads that read and write files to `jobs`.
+fn set_jobs(jobs: Option<usize>) {
+    if let Some(jobs) = jobs {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+        {
+            eprintln!("Warning: could not limit the number of jobs: {}", e);
+        }
+    }
+}
+
+/// Finds the samples below `source`, with a spinner if `progress` is set.
+fn scan(source: &Path, progress: bool) -> Vec<PathBuf> {
+    let progress = progress::Progress::spinner(progress, "Scanning");
+    let samples = sample_finder::find_samples_with_progress(source, &progress);
+    progress.finish();
+    samples
+}
+
+/// Starts a journal in `<dest>.journals/` next to `dest`, where `organize`
+/// keeps its journals out of the exported library. If that folder can't be
+/// written, the journal goes to the per-user state folder instead.
+fn create_default_journal(dest: &Path) -> Result<journal::Recorder, String> {
+    let name = journal_name();
+    let error = match journal::Recorder::create_unique(&journals_beside(dest).join(&name)) {
+        Ok(recorder) => return Ok(recorder),
+        Err(e) => e,
+    };
+    let Some(dir) = state_journals() else {
+        return Err(error);
+    };
+    eprintln!(
+        "Warning: {}; keeping the journal in {:?} instead.",
+        error, dir
+    );
+    journal::Recorder::create_unique(&dir.join(name))
+}
+
+/// `<unix time>.jsonl`, with nanoseconds so that runs in quick succession
+/// get their own journal.
+fn journal_name() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:09}.jsonl", now.as_secs(), now.subsec_nanos())
+}
+
+/// The `<dest>.journals` folder next to `dest`.
+fn journals_beside(dest: &Path) -> PathBuf {
+    let dest = std::path::absolute(dest).unwrap_or_else(|_| dest.to_path_buf());
+    match dest.file_name() {
+        Some(folder) => dest.with_file_name(format!("{}.journals", folder.to_string_lossy())),
+        None => dest.join(".journals"),
+    }
+}
+
+/// `sample_organizer/journals` in `$XDG_STATE_HOME`, or in `~/.local/state`
+/// if that isn't set.
+fn state_journals() -> Option<PathBuf> {
+    let absolute = |var| {
+        std::env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    let state = absolute("XDG_STATE_HOME")
+        .or_else(|| Some(absolute("HOME")?.join(".local").join("state")))?;
+    Some(state.join("sample_organizer").join("journals"))
+}
+
+/// Tells how to undo a run that created anything, or removes its journal if
+/// it didn't.
+fn finish_journal(recorder: journal::Recorder, journal: &journal::Journal) {
+    let path = recorder.path().to_path_buf();
+    drop(recorder);
+    if journal.entries.is_empty() && journal.folders.is_empty() {
+        let _ = std::fs::remove_file(&path);
+        return;
+    }
+    println!(
+        "Recorded this run in {:?}. Run `undo {}` to reverse it.",
+        path,
+        path.display()
+    );
+}
+
+/// Loads the config at `path`, or `config.toml` if `path` is `None`. Without
+/// either, the built-in categories are used.
+fn load_categorizer(
+    path: Option<&Path>,
+    profile: Option<&str>,
+) -> Option<categorizer::Categorizer> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+        None => {
+            eprintln!(
+                "No {} found, using the built-in categories (`--config {}`).",
+                DEFAULT_CONFIG,
+                config::BUILTIN
+            );
+            Path::new(config::BUILTIN)
+        }
+    };
+    let config = match config::load_config(path, profile) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error loading config: {}", e);
+            if matches!(&e, config::ConfigError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+            {
+                eprintln!(
+                    "Use `--config {}` for the built-in drum and instrument categories.",
+                    config::BUILTIN
+                );
+            }
+            return None;
+        }
+    };
+    match categorizer::Categorizer::new(&config) {
+        Ok(c) => Some(c),
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("Error in config {}: {}", path.display(), e);
             }
+            None
         }
     }
 }
@@ -0,0 +1,1349 @@
+use crate::audio_info::{self, AudioInfo};
+use crate::categorizer::{Categorizer, Decision};
+use crate::classifier::Classification;
+use crate::copier::{self, Mode, OnConflict, Resolution};
+use crate::journal::{Entry, Journal, Recorder};
+use crate::plan::{Duplicate, Operation, Plan};
+use crate::progress::{self, Progress};
+use crate::template::TemplateValues;
+use crate::utils::file_checksum;
+use natord::compare;
+use rayon::prelude::*;
+use std::collections::{hash_map, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Counts reported at the end of an `organize` run, or expected by a plan.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Samples placed in the destination, in whichever [`Mode`].
+    pub transferred: usize,
+    /// Samples no rule or classifier claimed, in the order they were processed.
+    pub uncategorized: Vec<PathBuf>,
+    /// Samples no rule matched that were sorted by their audio content.
+    pub classified: Vec<Classified>,
+    /// Samples whose destination was already taken, and what was done.
+    pub collisions: Vec<Collision>,
+    /// Samples left out because their category already has the same audio.
+    pub duplicates: Vec<Duplicate>,
+    /// Files and folders the run created, for `undo`.
+    pub journal: Journal,
+}
+
+/// A sample whose destination file already existed.
+#[derive(Debug, PartialEq)]
+pub struct Collision {
+    pub sample: PathBuf,
+    pub dest: PathBuf,
+    pub resolution: Resolution,
+}
+
+/// A sample the content classifier sorted.
+#[derive(Debug, PartialEq)]
+pub struct Classified {
+    pub sample: PathBuf,
+    pub category: String,
+    pub classification: Classification,
+}
+
+/// Settings of an `organize` run.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Folder inside the destination that uncategorized samples are placed
+    /// in, keeping their path below the source. They're left out if `None`.
+    pub uncategorized: Option<PathBuf>,
+    /// How samples are transferred into the destination.
+    pub mode: Mode,
+    /// What happens to samples whose destination is already taken.
+    pub on_conflict: OnConflict,
+    /// Place only the first of several samples in a category whose audio is
+    /// identical, by [`audio_info::audio_checksum`].
+    pub dedup: bool,
+    /// Show progress bars while samples are sorted and placed.
+    pub progress: bool,
+}
+
+/// Where one sample goes, relative to the destination root.
+struct Placement<'a> {
+    sample: &'a Path,
+    /// `None` for samples kept in the uncategorized folder.
+    category: Option<String>,
+    relative: PathBuf,
+    max_files_per_folder: Option<usize>,
+}
+
+/// Sorts `samples` into `dest`, placing each one where its category's
+/// template says, and records what was created with `journal`.
+pub fn organize(
+    categorizer: &Categorizer,
+    samples: &[PathBuf],
+    source: &Path,
+    dest: &Path,
+    options: &Options,
+    journal: Option<&Recorder>,
+) -> Summary {
+    let (plan, expected) = plan(categorizer, samples, source, dest, options);
+    Summary {
+        classified: expected.classified,
+        ..apply(&plan, options.progress, journal)
+    }
+}
+
+/// Works out where [`organize`] would put every sample and what it would do
+/// about files already in `dest`, without writing anything. The summary
+/// tells what applying the plan is expected to do.
+///
+/// Samples are processed in natural path order so `{index}` numbers are
+/// stable between runs over the same source.
+pub fn plan(
+    categorizer: &Categorizer,
+    samples: &[PathBuf],
+    source: &Path,
+    dest: &Path,
+    options: &Options,
+) -> (Plan, Summary) {
+    let mut samples: Vec<&PathBuf> = samples.iter().collect();
+    samples.sort_by(|a, b| compare(&a.to_string_lossy(), &b.to_string_lossy()));
+
+    // the slow part, reading the samples, runs in parallel
+    let progress = Progress::files(options.progress, "Sorting", samples.len());
+    let analyses: Vec<Analysis> = samples
+        .par_iter()
+        .map(|sample| {
+            let analysis = analyse(categorizer, sample, source, options.dedup);
+            progress.advance(0);
+            analysis
+        })
+        .collect();
+    progress.finish();
+
+    // only samples that share their category and data size with another one
+    // can be duplicates, so the others are never hashed
+    let mut sizes: HashMap<(&str, u64), usize> = HashMap::new();
+    for analysis in &analyses {
+        if let (Some(decision), Some(Ok(size))) = (&analysis.decision, &analysis.size) {
+            *sizes.entry((&decision.category.name, *size)).or_insert(0) += 1;
+        }
+    }
+    let colliding = |analysis: &Analysis| match (&analysis.decision, &analysis.size) {
+        (Some(decision), Some(Ok(size))) => sizes[&(decision.category.name.as_str(), *size)] > 1,
+        _ => false,
+    };
+    let hashed = analyses
+        .iter()
+        .filter(|analysis| colliding(analysis))
+        .count();
+    let progress = Progress::files(
+        options.progress && hashed > 0,
+        "Checking duplicates",
+        hashed,
+    );
+    let checksums: Vec<Option<Result<String, String>>> = samples
+        .par_iter()
+        .zip(&analyses)
+        .map(|(sample, analysis)| {
+            colliding(analysis).then(|| {
+                let checksum = checksum(sample);
+                progress.advance(0);
+                checksum
+            })
+        })
+        .collect();
+    progress.finish();
+
+    let mut summary = Summary::default();
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    // the first sample placed in a category with a given checksum
+    let mut originals: HashMap<(String, String), &PathBuf> = HashMap::new();
+    let mut placements = Vec::new();
+
+    for ((sample, analysis), checksum) in samples.into_iter().zip(analyses).zip(checksums) {
+        let Some(decision) = analysis.decision else {
+            summary.uncategorized.push(sample.clone());
+            if let Some(folder) = &options.uncategorized {
+                let relative = match sample.strip_prefix(source) {
+                    Ok(relative) => relative.to_path_buf(),
+                    Err(_) => PathBuf::from(sample.file_name().unwrap_or_default()),
+                };
+                placements.push(Placement {
+                    sample,
+                    category: None,
+                    relative: folder.join(relative),
+                    max_files_per_folder: None,
+                });
+            }
+            continue;
+        };
+        let category = decision.category;
+        if let Some(fuzzy) = &decision.fuzzy {
+            progress::println(format!(
+                "Fuzzy match {} for {:?} in '{}'",
+                fuzzy, sample, category.name
+            ));
+        }
+        if let Some(Err(e)) = &analysis.size {
+            eprintln!("Error checking {:?} for duplicates: {}", sample, e);
+        }
+        if let Some(checksum) = checksum {
+            match checksum {
+                Ok(checksum) => match originals.entry((category.name.clone(), checksum)) {
+                    hash_map::Entry::Occupied(original) => {
+                        summary.duplicates.push(Duplicate {
+                            sample: sample.clone(),
+                            original: original.get().to_path_buf(),
+                            category: category.name.clone(),
+                        });
+                        continue;
+                    }
+                    hash_map::Entry::Vacant(slot) => {
+                        slot.insert(sample);
+                    }
+                },
+                Err(e) => eprintln!("Error checking {:?} for duplicates: {}", sample, e),
+            }
+        }
+        if let Some(classification) = decision.content {
+            summary.classified.push(Classified {
+                sample: sample.clone(),
+                category: category.name.clone(),
+                classification,
+            });
+        }
+        let index = indices.entry(&category.name).or_insert(0);
+        *index += 1;
+
+        let info = analysis.info.and_then(|info| {
+            info.map_err(|e| eprintln!("Error reading {:?}: {}", sample, e))
+                .ok()
+        });
+        let filename = sample.file_name().unwrap_or_default().to_string_lossy();
+        let values = TemplateValues {
+            category: &category.name,
+            pack: &pack_name(sample, source),
+            stem: &sample.file_stem().unwrap_or_default().to_string_lossy(),
+            filename: &filename,
+            ext: &sample.extension().unwrap_or_default().to_string_lossy(),
+            index: *index,
+            info: info.as_ref(),
+        };
+
+        match category.template.render(&values) {
+            Ok(relative) => placements.push(Placement {
+                sample,
+                category: Some(category.name.clone()),
+                relative,
+                max_files_per_folder: category.max_files_per_folder,
+            }),
+            Err(e) => eprintln!("Error placing {:?}: {}", sample, e),
+        }
+    }
+
+    split_folders(&mut placements, dest);
+    let mut plan = Plan {
+        mode: options.mode,
+        on_conflict: options.on_conflict,
+        operations: Vec::new(),
+        uncategorized: summary.uncategorized.clone(),
+        duplicates: summary.duplicates.clone(),
+    };
+    // destinations claimed by earlier operations, and the samples claiming them
+    let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for placement in placements {
+        let dest_file = dest.join(&placement.relative);
+        let conflict =
+            match copier::decide(placement.sample, &dest_file, options.on_conflict, &claimed) {
+                Ok(conflict) => conflict,
+                Err(e) => {
+                    eprintln!("Error planning {:?}: {}", placement.sample, e);
+                    continue;
+                }
+            };
+        let target = match &conflict {
+            None | Some(Resolution::Overwritten) => Some(&dest_file),
+            Some(Resolution::Renamed(path)) => Some(path),
+            Some(_) => None,
+        };
+        if let Some(target) = target {
+            claimed.insert(target.clone(), placement.sample.to_path_buf());
+            summary.transferred += 1;
+        }
+        if let Some(resolution) = &conflict {
+            summary.collisions.push(Collision {
+                sample: placement.sample.to_path_buf(),
+                dest: dest_file.clone(),
+                resolution: resolution.clone(),
+            });
+        }
+        plan.operations.push(Operation {
+            source: placement.sample.to_path_buf(),
+            dest: dest_file,
+            category: placement.category,
+            conflict,
+        });
+    }
+    (plan, summary)
+}
+
+/// What [`plan`] needs to know about a sample before placing it.
+struct Analysis<'a> {
+    decision: Option<Decision<'a>>,
+    /// Set if duplicates are looked for and the sample has a category.
+    size: Option<Result<u64, String>>,
+    /// Set if the category's template needs the sample's audio properties.
+    info: Option<Result<AudioInfo, String>>,
+}
+
+fn analyse<'a>(
+    categorizer: &'a Categorizer,
+    sample: &Path,
+    source: &Path,
+    dedup: bool,
+) -> Analysis<'a> {
+    let decision = categorizer.decide(sample, source);
+    let category = decision.as_ref().map(|decision| decision.category);
+    Analysis {
+        size: category.filter(|_| dedup).map(|_| data_size(sample)),
+        info: category
+            .filter(|category| category.template.needs_audio_info())
+            .map(|_| audio_info::read_audio_info(sample)),
+        decision,
+    }
+}
+
+/// Carries out the operations of `plan` in order. Files and folders are
+/// recorded with `journal` as soon as they're created.
+///
+/// Conflicts are resolved as the plan says. A destination that was free when
+/// the plan was made but is taken now is handled by the plan's
+/// `on_conflict` policy.
+pub fn apply(plan: &Plan, progress: bool, journal: Option<&Recorder>) -> Summary {
+    let sizes: Vec<u64> = plan
+        .operations
+        .par_iter()
+        .map(|operation| fs::metadata(&operation.source).map_or(0, |m| m.len()))
+        .collect();
+    let progress = Progress::transfer(progress, "Placing", sizes.len(), sizes.iter().sum());
+
+    // Every target is worked out before anything is written, so operations
+    // that end up at the same path, even in a hand-edited plan, are found
+    let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let resolutions: Vec<Decided> = plan
+        .operations
+        .iter()
+        .map(|operation| {
+            let resolution = match &operation.conflict {
+                None => copier::decide(
+                    &operation.source,
+                    &operation.dest,
+                    plan.on_conflict,
+                    &claimed,
+                )?,
+                Some(resolution) => Some(resolution.clone()),
+            };
+            if let Some(target) = target(operation, resolution.as_ref()) {
+                claimed.insert(target.clone(), operation.source.clone());
+            }
+            Ok(resolution)
+        })
+        .collect();
+
+    // operations on the same target run one after another, in order, and
+    // different targets in parallel
+    let mut groups: Vec<Vec<(usize, Decided)>> = Vec::new();
+    let mut group_of: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, (operation, resolution)) in plan.operations.iter().zip(resolutions).enumerate() {
+        let key = match &resolution {
+            Ok(resolution) => target(operation, resolution.as_ref()).unwrap_or(&operation.dest),
+            Err(_) => &operation.dest,
+        };
+        let key = key.clone();
+        let group = *group_of.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push((i, resolution));
+    }
+    let mut outcomes: Vec<(usize, Outcome)> = groups
+        .into_par_iter()
+        .flat_map_iter(|group| {
+            group.into_iter().map(|(i, resolution)| {
+                let outcome =
+                    carry_out_operation(&plan.operations[i], resolution, plan.mode, journal);
+                progress.advance(sizes[i]);
+                (i, outcome)
+            })
+        })
+        .collect();
+    progress.finish();
+    outcomes.sort_by_key(|(i, _)| *i);
+
+    let mut summary = Summary {
+        uncategorized: plan.uncategorized.clone(),
+        duplicates: plan.duplicates.clone(),
+        ..Default::default()
+    };
+    let mut folders = HashSet::new();
+    for ((_, outcome), operation) in outcomes.into_iter().zip(&plan.operations) {
+        for folder in outcome.folders {
+            if folders.insert(folder.clone()) {
+                summary.journal.folders.push(folder);
+            }
+        }
+        summary.journal.entries.extend(outcome.entry);
+        for warning in outcome.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        match outcome.result {
+            Ok(None) => summary.transferred += 1,
+            Ok(Some(resolution)) => {
+                if !resolution.is_skip() {
+                    summary.transferred += 1;
+                }
+                summary.collisions.push(Collision {
+                    sample: operation.source.clone(),
+                    dest: operation.dest.clone(),
+                    resolution,
+                });
+            }
+            Err(e) => eprintln!("Error transferring file: {}", e),
+        }
+    }
+    summary
+}
+
+/// The resolution of an operation's conflict, or why it couldn't be decided.
+type Decided = Result<Option<Resolution>, String>;
+
+/// What carrying out one operation of a plan did.
+struct Outcome {
+    result: Decided,
+    /// Folders created for the placed file.
+    folders: Vec<PathBuf>,
+    /// The journal entry of the placed file, if one was placed.
+    entry: Option<Entry>,
+    /// Problems that didn't stop the file from being placed.
+    warnings: Vec<String>,
+}
+
+fn carry_out_operation(
+    operation: &Operation,
+    resolution: Decided,
+    mode: Mode,
+    journal: Option<&Recorder>,
+) -> Outcome {
+    let mut folders = Vec::new();
+    let mut warnings = Vec::new();
+    let placing = resolution
+        .as_ref()
+        .is_ok_and(|resolution| target(operation, resolution.as_ref()).is_some());
+    if let Some(parent) = operation.dest.parent().filter(|_| placing) {
+        // created here rather than by the copier, so they're journaled even
+        // if the transfer fails; errors show up when the file is placed
+        let missing = missing_folders(&operation.dest);
+        let _ = fs::create_dir_all(parent);
+        for folder in missing.iter().filter(|folder| folder.is_dir()) {
+            let folder = absolute(folder);
+            if let Some(Err(e)) = journal.map(|journal| journal.folder(&folder)) {
+                warnings.push(e);
+            }
+            folders.push(folder);
+        }
+    }
+
+    let result = resolution.and_then(|resolution| {
+        copier::carry_out(
+            &operation.source,
+            &operation.dest,
+            resolution.as_ref(),
+            mode,
+        )
+        .map(|()| resolution)
+    });
+    let mut entry = None;
+    if let Some(placed) = result
+        .as_ref()
+        .ok()
+        .and_then(|resolution| target(operation, resolution.as_ref()))
+    {
+        let replaced = matches!(result, Ok(Some(Resolution::Overwritten)));
+        match journal_entry(&operation.source, placed, mode, replaced) {
+            Ok(placed) => {
+                if let Some(Err(e)) = journal.map(|journal| journal.file(placed.clone())) {
+                    warnings.push(e);
+                }
+                entry = Some(placed);
+            }
+            Err(e) => warnings.push(e),
+        }
+    }
+    Outcome {
+        result,
+        folders,
+        entry,
+        warnings,
+    }
+}
+
+/// Where `operation` puts its sample given the `resolution` of its conflict,
+/// or `None` if the sample is skipped.
+fn target<'a>(operation: &'a Operation, resolution: Option<&'a Resolution>) -> Option<&'a PathBuf> {
+    match resolution {
+        None | Some(Resolution::Overwritten) => Some(&operation.dest),
+        Some(Resolution::Renamed(renamed)) => Some(renamed),
+        Some(_) => None,
+    }
+}
+
+/// [`audio_info::audio_checksum`] of `sample`, or the checksum of the whole
+/// file if it can't be parsed as a WAV file.
+fn checksum(sample: &Path) -> Result<String, String> {
+    audio_info::audio_checksum(sample).or_else(|_| file_checksum(sample).map_err(|e| e.to_string()))
+}
+
+/// [`audio_info::audio_data_size`] of `sample`, or the size of the whole
+/// file if it can't be parsed as a WAV file, to go with [`checksum`].
+fn data_size(sample: &Path) -> Result<u64, String> {
+    audio_info::audio_data_size(sample).or_else(|_| {
+        fs::metadata(sample)
+            .map(|metadata| metadata.len())
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// Folders above `dest` that don't exist yet, outermost first.
+fn missing_folders(dest: &Path) -> Vec<PathBuf> {
+    let mut folders: Vec<PathBuf> = dest
+        .ancestors()
+        .skip(1)
+        .take_while(|folder| !folder.as_os_str().is_empty() && !folder.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    folders.reverse();
+    folders
+}
+
+/// The journal entry of the file placed at `placed`. Paths are made
+/// absolute so the journal can be undone from anywhere.
+fn journal_entry(
+    source: &Path,
+    placed: &Path,
+    mode: Mode,
+    replaced: bool,
+) -> Result<Entry, String> {
+    let checksum = file_checksum(placed)
+        .map_err(|e| format!("{:?} can't be undone, it could not be read: {}", placed, e))?;
+    Ok(Entry {
+        path: absolute(placed),
+        source: absolute(source),
+        mode,
+        checksum,
+        link: match mode {
+            Mode::Symlink => fs::read_link(placed).ok(),
+            _ => None,
+        },
+        replaced,
+    })
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Writes the paths of `samples` to `path`, one per line.
+pub fn write_report(path: &Path, samples: &[PathBuf]) -> Result<(), String> {
+    let report: String = samples
+        .iter()
+        .map(|s| format!("{}\n", s.display()))
+        .collect();
+    fs::write(path, report).map_err(|e| format!("Failed to write report {:?}: {}", path, e))
+}
+
+/// Moves placements whose folder would hold more files than its limit into
+/// numbered folders, `kicks` becoming `kicks_01`, `kicks_02`, ...
+///
+/// Numbered folders already in `dest` are filled up before new ones are
+/// started, and samples that are already there stay in their folder, so
+/// repeated runs never shuffle files between folders. Samples placed in the
+/// plain folder before it had to be split stay there too.
+fn split_folders(placements: &mut [Placement], dest: &Path) {
+    let mut groups: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (i, placement) in placements.iter().enumerate() {
+        let Some(folder) = placement.relative.parent() else {
+            continue;
+        };
+        // files directly in `dest` have no folder to split
+        if placement.max_files_per_folder.is_some() && !folder.as_os_str().is_empty() {
+            groups.entry(folder.to_path_buf()).or_default().push(i);
+        }
+    }
+
+    for (folder, mut group) in groups {
+        let limit = placements[group[0]]
+            .max_files_per_folder
+            .unwrap_or(usize::MAX);
+        let folder_name = folder
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let numbered = |n: usize| folder.with_file_name(format!("{}_{:02}", folder_name, n));
+        // a name in a folder only keeps a sample there if it's that sample
+        let is_placed = |folder: &Path, files: &[OsString], name: &OsString, sample: &Path| {
+            files.contains(name) && copier::holds(&dest.join(folder).join(name), sample)
+        };
+
+        group.sort_by(|&a, &b| {
+            compare(
+                &placements[a].relative.to_string_lossy(),
+                &placements[b].relative.to_string_lossy(),
+            )
+        });
+        let plain = folder_files(&dest.join(&folder));
+        let members: Vec<(usize, OsString, &Path)> = group
+            .into_iter()
+            .map(|i| {
+                let name = placements[i].relative.file_name().unwrap_or_default();
+                (i, name.to_os_string(), placements[i].sample)
+            })
+            .filter(|(_, name, sample)| !is_placed(&folder, &plain, name, sample))
+            .collect();
+        let mut chunks = existing_chunks(&dest.join(&folder), &folder_name);
+        if chunks.is_empty() && plain.len() + members.len() <= limit {
+            continue;
+        }
+
+        for (i, name, sample) in members {
+            let placed =
+                (0..chunks.len()).find(|&c| is_placed(&numbered(c + 1), &chunks[c], &name, sample));
+            let chunk = match placed {
+                Some(chunk) => chunk,
+                None => {
+                    if chunks.last().is_none_or(|c| c.len() >= limit) {
+                        chunks.push(Vec::new());
+                    }
+                    chunks.last_mut().unwrap().push(name.clone());
+                    chunks.len() - 1
+                }
+            };
+            placements[i].relative = numbered(chunk + 1).join(name);
+        }
+    }
+}
+
+/// Names of the entries in `folder`, or none if it doesn't exist.
+fn folder_files(folder: &Path) -> Vec<OsString> {
+    fs::read_dir(folder)
+        .map(|entries| entries.flatten().map(|entry| entry.file_name()).collect())
+        .unwrap_or_default()
+}
+
+/// File names in the numbered folders `<folder_name>_NN` next to `folder`,
+/// by folder number starting at 1. Missing numbers get an empty list.
+fn existing_chunks(folder: &Path, folder_name: &str) -> Vec<Vec<OsString>> {
+    let mut chunks = Vec::new();
+    let Some(Ok(entries)) = folder.parent().map(fs::read_dir) else {
+        return chunks;
+    };
+    let prefix = format!("{}_", folder_name);
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let number = name
+            .strip_prefix(&prefix)
+            .and_then(|n| n.parse::<usize>().ok());
+        let (Some(number @ 1..), Ok(files)) = (number, fs::read_dir(entry.path())) else {
+            continue;
+        };
+        if chunks.len() < number {
+            chunks.resize_with(number, Vec::new);
+        }
+        chunks[number - 1].extend(files.flatten().map(|f| f.file_name()));
+    }
+    chunks
+}
+
+/// The first folder below `source` that contains `sample`, or the name of
+/// `source` itself for samples at the top level.
+fn pack_name(sample: &Path, source: &Path) -> String {
+    let relative = sample.strip_prefix(source).unwrap_or(sample);
+    let mut components = relative.components();
+    let first = components.next();
+    match first {
+        Some(first) if components.next().is_some() => {
+            first.as_os_str().to_string_lossy().into_owned()
+        }
+        _ => source
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Pattern, Rule};
+    use tempfile::tempdir;
+
+    fn config(template: &str) -> Config {
+        let rule: Rule = toml::from_str(&format!(
+            "category = \"drums/kicks\"\npatterns = [\"kick\"]\ntemplate = {:?}",
+            template
+        ))
+        .unwrap();
+        Config {
+            rules: vec![rule],
+            exclude: vec![Pattern::new("skip")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pack_name() {
+        let source = Path::new("/samples/in");
+        assert_eq!(
+            pack_name(Path::new("/samples/in/Pack A/Kicks/k.wav"), source),
+            "Pack A"
+        );
+        assert_eq!(pack_name(Path::new("/samples/in/k.wav"), source), "in");
+    }
+
+    #[test]
+    fn test_organize_renders_template() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::create_dir_all(source.path().join("Pack A")).unwrap();
+        for name in ["Kick 10.wav", "Kick 2.wav", "Snare.wav", "kick skip.wav"] {
+            fs::File::create(source.path().join("Pack A").join(name)).unwrap();
+        }
+
+        let categorizer =
+            Categorizer::new(&config("{category}/{pack}/{stem}_{index:02}.{ext}")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let mut summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+        let journal = std::mem::take(&mut summary.journal);
+
+        assert_eq!(
+            summary,
+            Summary {
+                transferred: 2,
+                uncategorized: vec![
+                    source.path().join("Pack A").join("Snare.wav"),
+                    source.path().join("Pack A").join("kick skip.wav"),
+                ],
+                classified: Vec::new(),
+                collisions: Vec::new(),
+                duplicates: Vec::new(),
+                journal: Journal::default(),
+            }
+        );
+        let folder = dest.path().join("drums").join("kicks").join("Pack A");
+        assert_eq!(journal.entries.len(), 2);
+        assert_eq!(journal.entries[0].path, folder.join("Kick 2_01.wav"));
+        assert_eq!(
+            journal.folders,
+            [
+                dest.path().join("drums"),
+                dest.path().join("drums").join("kicks"),
+                folder.clone()
+            ]
+        );
+        assert!(folder.join("Kick 2_01.wav").exists());
+        assert!(folder.join("Kick 10_02.wav").exists());
+    }
+
+    #[test]
+    fn test_organize_splits_folders_stably() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for i in [1, 2, 3, 10, 11] {
+            fs::File::create(source.path().join(format!("Kick {}.wav", i))).unwrap();
+        }
+        let mut config = config("{category}/{filename}");
+        config.max_files_per_folder = Some(2);
+        let categorizer = Categorizer::new(&config).unwrap();
+
+        let samples = crate::sample_finder::find_samples(source.path());
+        organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+        let drums = dest.path().join("drums");
+        assert!(drums.join("kicks_01").join("Kick 1.wav").exists());
+        assert!(drums.join("kicks_01").join("Kick 2.wav").exists());
+        assert!(drums.join("kicks_02").join("Kick 3.wav").exists());
+        assert!(drums.join("kicks_02").join("Kick 10.wav").exists());
+        assert!(drums.join("kicks_03").join("Kick 11.wav").exists());
+
+        // a new sample that sorts first must not push the others along
+        fs::File::create(source.path().join("Kick 0.wav")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+        assert!(drums.join("kicks_03").join("Kick 0.wav").exists());
+        assert!(!drums.join("kicks_04").exists());
+        assert_eq!(fs::read_dir(drums.join("kicks_01")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_organize_splits_only_full_folders() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for i in [1, 2] {
+            fs::write(source.path().join(format!("Kick {}.wav", i)), i.to_string()).unwrap();
+        }
+        let mut config = config("{category}/{filename}");
+        config.max_files_per_folder = Some(3);
+        let categorizer = Categorizer::new(&config).unwrap();
+        let run = || {
+            let samples = crate::sample_finder::find_samples(source.path());
+            let options = Options::default();
+            organize(
+                &categorizer,
+                &samples,
+                source.path(),
+                dest.path(),
+                &options,
+                None,
+            )
+        };
+
+        run();
+        let drums = dest.path().join("drums");
+        assert!(drums.join("kicks").join("Kick 1.wav").exists());
+        assert!(!drums.join("kicks_01").exists());
+
+        // once the folder would overflow, what's there stays and the rest
+        // goes into numbered folders
+        for i in [3, 4] {
+            fs::write(source.path().join(format!("Kick {}.wav", i)), i.to_string()).unwrap();
+        }
+        run();
+        assert_eq!(fs::read_dir(drums.join("kicks")).unwrap().count(), 2);
+        assert!(drums.join("kicks_01").join("Kick 3.wav").exists());
+        assert!(drums.join("kicks_01").join("Kick 4.wav").exists());
+    }
+
+    #[test]
+    fn test_organize_keeps_split_folders_within_the_limit() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for pack in ["Pack A", "Pack B"] {
+            fs::create_dir_all(source.path().join(pack)).unwrap();
+        }
+        for i in [1, 2, 3] {
+            let name = format!("Kick {}.wav", i);
+            fs::write(source.path().join("Pack A").join(name), format!("A {}", i)).unwrap();
+        }
+        let mut config = config("{category}/{filename}");
+        config.max_files_per_folder = Some(2);
+        let categorizer = Categorizer::new(&config).unwrap();
+        let run = || {
+            let samples = crate::sample_finder::find_samples(source.path());
+            let options = Options::default();
+            organize(
+                &categorizer,
+                &samples,
+                source.path(),
+                dest.path(),
+                &options,
+                None,
+            )
+        };
+
+        run();
+        let drums = dest.path().join("drums");
+        assert!(drums.join("kicks_01").join("Kick 1.wav").exists());
+        assert!(drums.join("kicks_02").join("Kick 3.wav").exists());
+
+        // a different sample named like one in a full folder goes elsewhere
+        fs::write(source.path().join("Pack B").join("Kick 1.wav"), "B 1").unwrap();
+        let summary = run();
+        assert_eq!(summary.transferred, 1);
+        assert_eq!(fs::read_dir(drums.join("kicks_01")).unwrap().count(), 2);
+        assert_eq!(
+            fs::read_to_string(drums.join("kicks_02").join("Kick 1.wav")).unwrap(),
+            "B 1"
+        );
+
+        let summary = run();
+        assert_eq!(summary.transferred, 0);
+        assert!(!drums.join("kicks_03").exists());
+    }
+
+    #[test]
+    fn test_organize_falls_back_to_content() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        // a low sine decaying within half a second, like a kick
+        let kick: Vec<f32> = (0..22050)
+            .map(|i| {
+                let t = i as f32 / 44100.0;
+                (t * 55.0 * std::f32::consts::TAU).sin() * (-t / 0.1).exp() * 0.8
+            })
+            .collect();
+        crate::audio_info::write_test_signal(&source.path().join("001.wav"), 44100, &kick);
+        fs::File::create(source.path().join("002.wav")).unwrap();
+
+        let mut config = config("{category}/{index:03}.{ext}");
+        config.classifier = Some(toml::from_str("kick = \"drums/kicks\"").unwrap());
+        let categorizer = Categorizer::new(&config).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+
+        // the kick shares the template of the rule with the same category
+        assert!(dest
+            .path()
+            .join("drums")
+            .join("kicks")
+            .join("001.wav")
+            .exists());
+        assert_eq!((summary.transferred, summary.uncategorized.len()), (1, 1));
+        assert_eq!(summary.classified.len(), 1);
+        assert_eq!(summary.classified[0].category, "drums/kicks");
+        assert_eq!(
+            summary.classified[0].classification.class,
+            crate::classifier::SoundClass::Kick
+        );
+    }
+
+    #[test]
+    fn test_organize_keeps_uncategorized() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        fs::create_dir_all(source.path().join("Pack A")).unwrap();
+        for name in ["Kick.wav", "Pad.wav", "skip kick.wav"] {
+            fs::File::create(source.path().join("Pack A").join(name)).unwrap();
+        }
+
+        let categorizer = Categorizer::new(&config("{category}/{filename}")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let options = Options {
+            uncategorized: Some(PathBuf::from("_uncategorized")),
+            ..Default::default()
+        };
+        let summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &options,
+            None,
+        );
+
+        assert_eq!(summary.transferred, 3);
+        let kept = dest.path().join("_uncategorized").join("Pack A");
+        assert!(kept.join("Pad.wav").exists());
+        assert!(kept.join("skip kick.wav").exists());
+
+        let report = dest.path().join("report.txt");
+        write_report(&report, &summary.uncategorized).unwrap();
+        let pack = source.path().join("Pack A");
+        assert_eq!(
+            fs::read_to_string(&report).unwrap(),
+            format!(
+                "{}\n{}\n",
+                pack.join("Pad.wav").display(),
+                pack.join("skip kick.wav").display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_organize_places_duplicates_once() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for pack in ["Pack A", "Pack B"] {
+            fs::create_dir_all(source.path().join(pack)).unwrap();
+        }
+        let kick = [0.9, -0.6, 0.3, -0.1];
+        audio_info::write_test_signal(&source.path().join("Pack A").join("Kick.wav"), 8000, &kick);
+        audio_info::write_test_signal(
+            &source.path().join("Pack B").join("Kick Hard.wav"),
+            8000,
+            &kick,
+        );
+        audio_info::write_test_signal(
+            &source.path().join("Pack B").join("Kick Soft.wav"),
+            8000,
+            &[0.5, -0.2],
+        );
+        // as long as the kick, so it's hashed too
+        let loud = [1.0, -0.6, 0.3, -0.1];
+        audio_info::write_test_signal(
+            &source.path().join("Pack B").join("Kick Loud.wav"),
+            8000,
+            &loud,
+        );
+
+        let categorizer = Categorizer::new(&config("{category}/{stem}_{index:02}.{ext}")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let options = Options {
+            dedup: true,
+            ..Default::default()
+        };
+        let (plan, _) = plan(&categorizer, &samples, source.path(), dest.path(), &options);
+        let summary = apply(&plan, false, None);
+
+        let kicks = dest.path().join("drums").join("kicks");
+        assert_eq!(summary.transferred, 3);
+        assert!(kicks.join("Kick_01.wav").exists());
+        assert!(kicks.join("Kick Loud_02.wav").exists());
+        assert!(kicks.join("Kick Soft_03.wav").exists());
+        assert_eq!(
+            summary.duplicates,
+            [Duplicate {
+                sample: source.path().join("Pack B").join("Kick Hard.wav"),
+                original: source.path().join("Pack A").join("Kick.wav"),
+                category: "drums/kicks".to_string(),
+            }]
+        );
+
+        let summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+        assert!(summary.duplicates.is_empty());
+        assert!(kicks.join("Kick Hard_02.wav").exists());
+    }
+
+    #[test]
+    fn test_organize_reports_collisions() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for pack in ["Pack A", "Pack B", "Pack C"] {
+            fs::create_dir_all(source.path().join(pack)).unwrap();
+            fs::write(source.path().join(pack).join("Kick 01.wav"), pack).unwrap();
+        }
+        // the same bytes as Pack A's kick
+        fs::write(source.path().join("Pack C").join("Kick 01.wav"), "Pack A").unwrap();
+
+        let categorizer = Categorizer::new(&config("{category}/{filename}")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let options = Options {
+            on_conflict: OnConflict::Compare,
+            ..Default::default()
+        };
+        let summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &options,
+            None,
+        );
+
+        let kicks = dest.path().join("drums").join("kicks");
+        assert_eq!(summary.transferred, 2);
+        let resolutions: Vec<_> = summary
+            .collisions
+            .iter()
+            .map(|c| c.resolution.clone())
+            .collect();
+        assert_eq!(
+            resolutions,
+            [
+                Resolution::Renamed(kicks.join("Kick 01 (2).wav")),
+                Resolution::Identical
+            ]
+        );
+        assert_eq!(
+            summary.collisions[1].sample,
+            source.path().join("Pack C").join("Kick 01.wav")
+        );
+        assert_eq!(
+            fs::read_to_string(kicks.join("Kick 01 (2).wav")).unwrap(),
+            "Pack B"
+        );
+    }
+
+    #[test]
+    fn test_plan_writes_nothing_until_applied() {
+        let source = tempdir().unwrap();
+        let dest = tempdir().unwrap();
+        for pack in ["Pack A", "Pack B"] {
+            fs::create_dir_all(source.path().join(pack)).unwrap();
+            fs::write(source.path().join(pack).join("Kick.wav"), pack).unwrap();
+        }
+        fs::write(source.path().join("Pad.wav"), "pad").unwrap();
+
+        let categorizer = Categorizer::new(&config("{category}/{filename}")).unwrap();
+        let samples = crate::sample_finder::find_samples(source.path());
+        let options = Options {
+            on_conflict: OnConflict::Rename,
+            ..Default::default()
+        };
+        let (mut plan, expected) =
+            plan(&categorizer, &samples, source.path(), dest.path(), &options);
+
+        assert_eq!(fs::read_dir(dest.path()).unwrap().count(), 0);
+        let kicks = dest.path().join("drums").join("kicks");
+        let dests: Vec<_> = plan
+            .operations
+            .iter()
+            .map(|o| (o.dest.clone(), o.conflict.clone()))
+            .collect();
+        assert_eq!(
+            dests,
+            [
+                (kicks.join("Kick.wav"), None),
+                (
+                    kicks.join("Kick.wav"),
+                    Some(Resolution::Renamed(kicks.join("Kick (2).wav")))
+                ),
+            ]
+        );
+        assert_eq!(plan.uncategorized, [source.path().join("Pad.wav")]);
+        assert_eq!((expected.transferred, expected.collisions.len()), (2, 1));
+
+        // a reviewer decides the second kick should replace the first
+        plan.operations[1].conflict = Some(Resolution::Overwritten);
+        let summary = apply(&plan, false, None);
+        assert_eq!(summary.transferred, 2);
+        assert_eq!(
+            fs::read_to_string(kicks.join("Kick.wav")).unwrap(),
+            "Pack B"
+        );
+        assert!(!kicks.join("Kick (2).wav").exists());
+    }
+
+    #[test]
+    fn test_apply_keeps_colliding_targets_apart() {
+        let dir = tempdir().unwrap();
+        let kicks = dir.path().join("out").join("kicks");
+        let sample = |name: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            path
+        };
+        let operation = |source: PathBuf, dest: &str, conflict| Operation {
+            source,
+            dest: kicks.join(dest),
+            category: Some("kicks".to_string()),
+            conflict,
+        };
+        // hand-edited: the first operation is renamed onto the second one's
+        // destination, and the third one onto the same name
+        let plan = Plan {
+            mode: Mode::Copy,
+            on_conflict: OnConflict::Rename,
+            operations: vec![
+                operation(
+                    sample("a.wav"),
+                    "Kick.wav",
+                    Some(Resolution::Renamed(kicks.join("Kick (2).wav"))),
+                ),
+                operation(sample("b.wav"), "Kick (2).wav", None),
+                operation(
+                    sample("c.wav"),
+                    "Kick.wav",
+                    Some(Resolution::Renamed(kicks.join("Kick (2).wav"))),
+                ),
+            ],
+            uncategorized: Vec::new(),
+            duplicates: Vec::new(),
+        };
+
+        let summary = apply(&plan, false, None);
+        assert_eq!(summary.transferred, 2);
+        assert_eq!(
+            fs::read_to_string(kicks.join("Kick (2).wav")).unwrap(),
+            "a.wav"
+        );
+        assert_eq!(
+            fs::read_to_string(kicks.join("Kick (2) (2).wav")).unwrap(),
+            "b.wav"
+        );
+        assert_eq!(
+            summary.collisions[1].resolution,
+            Resolution::Renamed(kicks.join("Kick (2) (2).wav"))
+        );
+        assert_eq!(summary.journal.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_journals_folders_of_failed_transfers() {
+        let dir = tempdir().unwrap();
+        let kicks = dir.path().join("out").join("kicks");
+        fs::write(dir.path().join("a.wav"), "a").unwrap();
+        let operation = |source: &str, dest: &str| Operation {
+            source: dir.path().join(source),
+            dest: kicks.join(dest),
+            category: Some("kicks".to_string()),
+            conflict: None,
+        };
+        let plan = Plan {
+            mode: Mode::Copy,
+            on_conflict: OnConflict::Skip,
+            operations: vec![
+                operation("a.wav", "a.wav"),
+                operation("missing.wav", "sub/missing.wav"),
+            ],
+            uncategorized: Vec::new(),
+            duplicates: Vec::new(),
+        };
+        let recorder = Recorder::create(&dir.path().join("run.jsonl")).unwrap();
+
+        let summary = apply(&plan, false, Some(&recorder));
+        assert_eq!(summary.transferred, 1);
+        let out = dir.path().join("out");
+        let folders = [out.clone(), kicks.clone(), kicks.join("sub")];
+        assert_eq!(summary.journal.folders, folders);
+
+        // the journal on disk has the same records, and undo cleans up
+        let journal = crate::journal::read_journal(recorder.path()).unwrap();
+        assert_eq!(journal.entries, summary.journal.entries);
+        assert_eq!(
+            journal.folders.iter().collect::<HashSet<_>>(),
+            folders.iter().collect()
+        );
+        assert_eq!(crate::journal::undo(&journal).undone, 1);
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_organize_keeps_a_fixed_order() {
+        let source = tempdir().unwrap();
+        for pack in ["Pack A", "Pack B", "Pack C"] {
+            fs::create_dir_all(source.path().join(pack)).unwrap();
+            for n in 1..=20 {
+                fs::write(
+                    source.path().join(pack).join(format!("Kick {}.wav", n)),
+                    format!("{} {}", pack, n),
+                )
+                .unwrap();
+                fs::File::create(source.path().join(pack).join(format!("Pad {}.wav", n))).unwrap();
+            }
+        }
+        let categorizer = Categorizer::new(&config("{category}/{stem}_{index:02}.{ext}")).unwrap();
+        let mut samples = crate::sample_finder::find_samples(source.path());
+        samples.sort();
+        samples.reverse();
+        let root = source.path();
+        let natural = |name: &str| -> Vec<PathBuf> {
+            ["Pack A", "Pack B", "Pack C"]
+                .iter()
+                .flat_map(|pack| {
+                    (1..=20).map(move |n| root.join(pack).join(format!("{} {}.wav", name, n)))
+                })
+                .collect()
+        };
+
+        let dest = tempdir().unwrap();
+        let summary = organize(
+            &categorizer,
+            &samples,
+            source.path(),
+            dest.path(),
+            &Options::default(),
+            None,
+        );
+        let kicks = dest.path().join("drums").join("kicks");
+        let sources: Vec<PathBuf> = summary
+            .journal
+            .entries
+            .iter()
+            .map(|entry| entry.source.clone())
+            .collect();
+        assert_eq!(sources, natural("Kick"));
+        for (i, entry) in summary.journal.entries.iter().enumerate() {
+            let stem = entry.source.file_stem().unwrap().to_string_lossy();
+            assert_eq!(entry.path, kicks.join(format!("{}_{:02}.wav", stem, i + 1)));
+        }
+        assert_eq!(summary.uncategorized, natural("Pad"));
+
+        // a second run over the same samples comes out the same
+        let again = tempdir().unwrap();
+        let (first, _) = plan(
+            &categorizer,
+            &samples,
+            source.path(),
+            again.path(),
+            &Options::default(),
+        );
+        let (second, _) = plan(
+            &categorizer,
+            &samples,
+            source.path(),
+            again.path(),
+            &Options::default(),
+        );
+        assert_eq!(first, second);
+        let summary = apply(&first, false, None);
+        let placed: Vec<&PathBuf> = summary
+            .journal
+            .entries
+            .iter()
+            .map(|entry| &entry.source)
+            .collect();
+        let planned: Vec<&PathBuf> = first
+            .operations
+            .iter()
+            .map(|operation| &operation.source)
+            .collect();
+        assert_eq!(placed, planned);
+    }
+
+    #[test]
+    fn test_apply_serializes_operations_on_one_dest() {
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("out").join("Kick.wav");
+        let operations = (0..32)
+            .map(|n| {
+                let source = dir.path().join(format!("{}.wav", n));
+                fs::write(&source, n.to_string()).unwrap();
+                Operation {
+                    source,
+                    dest: dest.clone(),
+                    category: Some("kicks".to_string()),
+                    conflict: Some(Resolution::Overwritten),
+                }
+            })
+            .collect();
+        let plan = Plan {
+            mode: Mode::Copy,
+            on_conflict: OnConflict::Overwrite,
+            operations,
+            uncategorized: Vec::new(),
+            duplicates: Vec::new(),
+        };
+
+        let summary = apply(&plan, false, None);
+        assert_eq!(summary.transferred, 32);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "31");
+        // each checksum is taken before the next operation replaces the file
+        for (n, entry) in summary.journal.entries.iter().enumerate() {
+            assert_eq!(entry.source, dir.path().join(format!("{}.wav", n)));
+            assert_eq!(entry.checksum, file_checksum(&entry.source).unwrap());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::copier::{Mode, OnConflict, Resolution};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Every file operation of an `organize` run, worked out before anything is
+/// written. Saved as JSON by `organize --plan` and carried out by `apply`,
+/// possibly after being edited by hand.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    pub mode: Mode,
+    /// Used for destinations that were free when the plan was made but are
+    /// taken by the time it's applied.
+    pub on_conflict: OnConflict,
+    pub operations: Vec<Operation>,
+    /// Samples that matched no category and are left out.
+    #[serde(default)]
+    pub uncategorized: Vec<PathBuf>,
+    /// Samples left out because the same audio is already placed in their
+    /// category.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<Duplicate>,
+}
+
+/// A sample whose audio is identical to one placed earlier in the same
+/// category.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Duplicate {
+    pub sample: PathBuf,
+    /// The sample that is placed instead.
+    pub original: PathBuf,
+    pub category: String,
+}
+
+/// One sample and where it goes.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Operation {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// The sample's category, or `None` if it goes to the uncategorized folder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// What is done about the file already at `dest`, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<Resolution>,
+}
+
+pub fn write_plan(path: &Path, plan: &Plan) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(plan)
+        .map_err(|e| format!("Failed to serialize plan: {}", e))?;
+    fs::write(path, json + "\n").map_err(|e| format!("Failed to write plan {:?}: {}", path, e))
+}
+
+pub fn read_plan(path: &Path) -> Result<Plan, String> {
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read plan {:?}: {}", path, e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Invalid plan {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        let plan = Plan {
+            mode: Mode::Hardlink,
+            on_conflict: OnConflict::Rename,
+            operations: vec![
+                Operation {
+                    source: PathBuf::from("in/Kick.wav"),
+                    dest: PathBuf::from("out/kicks/Kick.wav"),
+                    category: Some("kicks".to_string()),
+                    conflict: Some(Resolution::Renamed(PathBuf::from("out/kicks/Kick (2).wav"))),
+                },
+                Operation {
+                    source: PathBuf::from("in/Pad.wav"),
+                    dest: PathBuf::from("out/_uncategorized/Pad.wav"),
+                    category: None,
+                    conflict: None,
+                },
+            ],
+            uncategorized: vec![PathBuf::from("in/Pad.wav")],
+            duplicates: vec![Duplicate {
+                sample: PathBuf::from("in/B/Kick.wav"),
+                original: PathBuf::from("in/Kick.wav"),
+                category: "kicks".to_string(),
+            }],
+        };
+
+        write_plan(&path, &plan).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"mode\": \"hardlink\""));
+        assert!(json
+            .contains("\"conflict\": {\n        \"renamed\": \"out/kicks/Kick (2).wav\"\n      }"));
+        assert_eq!(read_plan(&path).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_read_plan_accepts_hand_edits() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        fs::write(
+            &path,
+            r#"{ "mode": "copy", "on_conflict": "skip", "operations": [
+                 { "source": "a.wav", "dest": "out/a.wav", "conflict": "overwritten" } ] }"#,
+        )
+        .unwrap();
+        let plan = read_plan(&path).unwrap();
+        assert_eq!(plan.operations[0].conflict, Some(Resolution::Overwritten));
+        assert!(plan.uncategorized.is_empty() && plan.duplicates.is_empty());
+
+        fs::write(
+            &path,
+            r#"{ "mode": "teleport", "on_conflict": "skip", "operations": [] }"#,
+        )
+        .unwrap();
+        let err = read_plan(&path).unwrap_err();
+        assert!(
+            err.starts_with("Invalid plan") && err.contains("line 1"),
+            "{}",
+            err
+        );
+    }
+}
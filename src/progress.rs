@@ -0,0 +1,124 @@
+use indicatif::{HumanCount, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The bar on screen, if any, so [`println`] can print above it.
+static SHOWN: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Progress display for one phase of a run, drawn on stderr. Hidden ones
+/// cost next to nothing, so callers can always report to one.
+pub struct Progress {
+    bar: ProgressBar,
+    files: AtomicU64,
+    total_files: u64,
+    /// Whether the bar's length is in bytes rather than files.
+    counts_bytes: bool,
+}
+
+impl Progress {
+    pub fn hidden() -> Self {
+        Progress {
+            bar: ProgressBar::hidden(),
+            files: AtomicU64::new(0),
+            total_files: 0,
+            counts_bytes: false,
+        }
+    }
+
+    /// A spinner for work of unknown length, such as scanning a library.
+    pub fn spinner(shown: bool, message: &str) -> Self {
+        let bar = ProgressBar::new_spinner().with_message(message.to_string());
+        bar.set_style(
+            ProgressStyle::with_template("{spinner} {msg} {human_pos} files, {elapsed}").unwrap(),
+        );
+        Self::show(shown, bar, 0, false)
+    }
+
+    /// A bar counting `files` files, with their rate and the time left.
+    pub fn files(shown: bool, message: &str, files: usize) -> Self {
+        let bar = ProgressBar::new(files as u64).with_message(message.to_string());
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{bar:30}] {human_pos}/{human_len} files ({per_sec}), ETA {eta}",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        );
+        Self::show(shown, bar, files as u64, false)
+    }
+
+    /// A bar for `files` files of `bytes` bytes in total, showing files/s,
+    /// bytes/s and the time left.
+    pub fn transfer(shown: bool, message: &str, files: usize, bytes: u64) -> Self {
+        let bar = ProgressBar::new(bytes).with_message(message.to_string());
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{bar:30}] {prefix}, {bytes}/{total_bytes} ({binary_bytes_per_sec}), ETA {eta}",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        );
+        let progress = Self::show(shown, bar, files as u64, true);
+        progress.update_prefix(0);
+        progress
+    }
+
+    fn show(shown: bool, bar: ProgressBar, total_files: u64, counts_bytes: bool) -> Self {
+        if shown {
+            bar.enable_steady_tick(Duration::from_millis(100));
+            *SHOWN.lock().unwrap() = Some(bar.clone());
+        } else {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        Progress {
+            bar,
+            files: AtomicU64::new(0),
+            total_files,
+            counts_bytes,
+        }
+    }
+
+    /// Counts one more file, of `bytes` bytes for [`Progress::transfer`].
+    pub fn advance(&self, bytes: u64) {
+        let files = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.counts_bytes {
+            self.bar.inc(bytes);
+            self.update_prefix(files);
+        } else {
+            self.bar.inc(1);
+        }
+    }
+
+    fn update_prefix(&self, files: u64) {
+        let seconds = self.bar.elapsed().as_secs_f64();
+        let rate = if seconds > 0.0 {
+            files as f64 / seconds
+        } else {
+            0.0
+        };
+        self.bar.set_prefix(format!(
+            "{}/{} files ({:.0}/s)",
+            HumanCount(files),
+            HumanCount(self.total_files),
+            rate
+        ));
+    }
+
+    /// Takes the bar off the screen.
+    pub fn finish(self) {
+        self.bar.finish_and_clear();
+        if !self.bar.is_hidden() {
+            *SHOWN.lock().unwrap() = None;
+        }
+    }
+}
+
+/// Prints `line` to stdout, above the progress bar if one is shown.
+pub fn println(line: impl Display) {
+    match SHOWN.lock().unwrap().as_ref() {
+        Some(bar) => bar.suspend(|| println!("{}", line)),
+        None => println!("{}", line),
+    }
+}
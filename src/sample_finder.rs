@@ -1,19 +1,56 @@
+use crate::progress::Progress;
+use rayon::prelude::*;
+use std::fs;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 
+/// All `.wav` files below `source`, in no particular order. Symlinks inside
+/// `source` are not followed.
 pub fn find_samples(source: &Path) -> Vec<PathBuf> {
-    WalkDir::new(source)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| {
-            e.path()
-                .extension()
-                .map(|ext| ext.eq_ignore_ascii_case("wav"))
-                .unwrap_or(false)
-        })
-        .map(|e| e.path().to_path_buf())
-        .collect()
+    find_samples_with_progress(source, &Progress::hidden())
+}
+
+/// [`find_samples`], counting every sample found on `progress`. Folders are
+/// read in parallel, which pays off on network drives.
+pub fn find_samples_with_progress(source: &Path, progress: &Progress) -> Vec<PathBuf> {
+    if source.is_file() {
+        return [source.to_path_buf()]
+            .into_iter()
+            .filter(|path| is_sample(path))
+            .collect();
+    }
+    walk(source, progress)
+}
+
+fn walk(folder: &Path, progress: &Progress) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut samples = Vec::new();
+    let mut folders = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            folders.push(path);
+        } else if file_type.is_file() && is_sample(&path) {
+            progress.advance(0);
+            samples.push(path);
+        }
+    }
+    let nested: Vec<Vec<PathBuf>> = folders
+        .par_iter()
+        .map(|folder| walk(folder, progress))
+        .collect();
+    samples.extend(nested.into_iter().flatten());
+    samples
+}
+
+fn is_sample(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("wav"))
+        .unwrap_or(false)
 }
 
 #[cfg(test)]
@@ -68,4 +105,42 @@ mod tests {
         assert!(samples.contains(&dir.path().join("a/b/sample_b.wav")));
         assert!(samples.contains(&dir.path().join("a/b/c/sample_c.wav")));
     }
-}
\ No newline at end of file
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_samples_matches_a_serial_walk() {
+        let dir = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        fs::File::create(outside.path().join("linked.wav")).unwrap();
+        for pack in 0..8 {
+            let folder = dir.path().join(format!("Pack {}", pack)).join("Drums");
+            fs::create_dir_all(&folder).unwrap();
+            for name in ["Kick.wav", "Snare.WAV", "Hat.Wav", "notes.txt", "wav"] {
+                fs::File::create(folder.join(name)).unwrap();
+            }
+        }
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("linked folder")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("linked.wav"),
+            dir.path().join("linked.wav"),
+        )
+        .unwrap();
+
+        // what the walker did before folders were read in parallel
+        let mut serial: Vec<PathBuf> = walkdir::WalkDir::new(dir.path())
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && is_sample(e.path()))
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        let mut samples = find_samples(dir.path());
+        serial.sort();
+        samples.sort();
+        assert_eq!(serial.len(), 24);
+        assert_eq!(samples, serial);
+
+        let single = dir.path().join("Pack 0").join("Drums").join("Kick.wav");
+        assert_eq!(find_samples(&single), [single]);
+    }
+}
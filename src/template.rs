@@ -0,0 +1,298 @@
+use crate::audio_info::AudioInfo;
+use std::path::PathBuf;
+
+/// Used when neither the category nor the config sets a template; keeps the
+/// original file name inside the category folder.
+pub const DEFAULT_TEMPLATE: &str = "{category}/{filename}";
+
+/// A destination path template such as `{category}/{pack}/{stem}_{index:03}.wav`.
+///
+/// Placeholders: `category`, `pack`, `stem`, `filename`, `ext`, `index`,
+/// `sample_rate` and `duration`. Numbers accept a zero-padded width (`:03`),
+/// `duration` a precision (`:.1`). `{{` and `}}` produce literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder, Spec),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Category,
+    Pack,
+    Stem,
+    Filename,
+    Ext,
+    Index,
+    SampleRate,
+    Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Spec {
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+/// Everything a template can refer to for one sample.
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateValues<'a> {
+    pub category: &'a str,
+    /// First folder below the source directory, e.g. the sample pack's name.
+    pub pack: &'a str,
+    pub stem: &'a str,
+    pub filename: &'a str,
+    pub ext: &'a str,
+    /// 1-based running number of the sample within its category.
+    pub index: usize,
+    /// Only needed if [`Template::needs_audio_info`] is true.
+    pub info: Option<&'a AudioInfo>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or("unclosed '{'")?;
+                    let (name, spec) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+                    let name = name.trim();
+                    let placeholder = parse_placeholder(name)?;
+                    let spec = parse_spec(name, placeholder, spec)?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder, spec));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("unmatched '}', use '}}' for a literal brace".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Whether rendering needs the sample's WAV header.
+    pub fn needs_audio_info(&self) -> bool {
+        self.segments.iter().any(|s| {
+            matches!(
+                s,
+                Segment::Placeholder(Placeholder::SampleRate | Placeholder::Duration, _)
+            )
+        })
+    }
+
+    /// Renders the destination path, relative to the destination root.
+    pub fn render(&self, values: &TemplateValues) -> Result<PathBuf, String> {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Placeholder(placeholder, spec) => {
+                    rendered.push_str(&render_placeholder(*placeholder, *spec, values)?)
+                }
+            }
+        }
+
+        let mut path = PathBuf::new();
+        for part in rendered.split('/').filter(|p| !p.is_empty()) {
+            if part == "." || part == ".." {
+                return Err(format!(
+                    "rendered path {:?} must not contain '{}'",
+                    rendered, part
+                ));
+            }
+            path.push(part);
+        }
+        if rendered.ends_with('/') || path.as_os_str().is_empty() {
+            return Err(format!("rendered path {:?} has no file name", rendered));
+        }
+        Ok(path)
+    }
+}
+
+fn parse_placeholder(name: &str) -> Result<Placeholder, String> {
+    Ok(match name {
+        "category" => Placeholder::Category,
+        "pack" => Placeholder::Pack,
+        "stem" => Placeholder::Stem,
+        "filename" => Placeholder::Filename,
+        "ext" => Placeholder::Ext,
+        "index" => Placeholder::Index,
+        "sample_rate" => Placeholder::SampleRate,
+        "duration" => Placeholder::Duration,
+        other => return Err(format!("unknown placeholder '{{{}}}'", other)),
+    })
+}
+
+fn parse_spec(name: &str, placeholder: Placeholder, spec: &str) -> Result<Spec, String> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Ok(Spec::default());
+    }
+    let invalid = || format!("invalid format ':{}'", spec);
+
+    match placeholder {
+        Placeholder::Index | Placeholder::SampleRate => {
+            let width = spec.strip_prefix('0').ok_or_else(invalid)?;
+            Ok(Spec {
+                width: Some(width.parse().map_err(|_| invalid())?),
+                precision: None,
+            })
+        }
+        Placeholder::Duration => {
+            let precision = spec.strip_prefix('.').ok_or_else(invalid)?;
+            Ok(Spec {
+                width: None,
+                precision: Some(precision.parse().map_err(|_| invalid())?),
+            })
+        }
+        _ => Err(format!("'{{{}}}' takes no format", name)),
+    }
+}
+
+fn render_placeholder(
+    placeholder: Placeholder,
+    spec: Spec,
+    values: &TemplateValues,
+) -> Result<String, String> {
+    let info = || {
+        values
+            .info
+            .ok_or("the sample's WAV header could not be read")
+    };
+    Ok(match placeholder {
+        Placeholder::Category => values.category.to_string(),
+        Placeholder::Pack => values.pack.to_string(),
+        Placeholder::Stem => values.stem.to_string(),
+        Placeholder::Filename => values.filename.to_string(),
+        Placeholder::Ext => values.ext.to_string(),
+        Placeholder::Index => format!("{:0width$}", values.index, width = spec.width.unwrap_or(0)),
+        Placeholder::SampleRate => format!(
+            "{:0width$}",
+            info()?.sample_rate,
+            width = spec.width.unwrap_or(0)
+        ),
+        Placeholder::Duration => format!(
+            "{:.prec$}",
+            info()?.duration(),
+            prec = spec.precision.unwrap_or(2)
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn values<'a>(info: Option<&'a AudioInfo>) -> TemplateValues<'a> {
+        TemplateValues {
+            category: "drums/kicks",
+            pack: "Pack A",
+            stem: "Kick 01",
+            filename: "Kick 01.wav",
+            ext: "wav",
+            index: 7,
+            info,
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = Template::parse(DEFAULT_TEMPLATE).unwrap();
+        let path = template.render(&values(None)).unwrap();
+        assert_eq!(path, Path::new("drums").join("kicks").join("Kick 01.wav"));
+    }
+
+    #[test]
+    fn test_render_all_placeholders() {
+        let info = AudioInfo {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            frames: 66150,
+        };
+        let template = Template::parse(
+            "{category}/{pack}/{stem}_{index:03}_{sample_rate}_{duration:.1}s.{ext}",
+        )
+        .unwrap();
+        assert!(template.needs_audio_info());
+
+        let path = template.render(&values(Some(&info))).unwrap();
+        assert_eq!(
+            path,
+            Path::new("drums")
+                .join("kicks")
+                .join("Pack A")
+                .join("Kick 01_007_44100_1.5s.wav")
+        );
+    }
+
+    #[test]
+    fn test_render_literal_braces() {
+        let template = Template::parse("{{{index}}}.wav").unwrap();
+        assert!(!template.needs_audio_info());
+        assert_eq!(
+            template.render(&values(None)).unwrap(),
+            Path::new("{7}.wav")
+        );
+    }
+
+    #[test]
+    fn test_render_needs_header() {
+        let template = Template::parse("{category}/{duration}.wav").unwrap();
+        let err = template.render(&values(None)).unwrap_err();
+        assert!(err.contains("WAV header"));
+    }
+
+    #[test]
+    fn test_render_rejects_parent_components() {
+        let template = Template::parse("../{filename}").unwrap();
+        assert!(template.render(&values(None)).is_err());
+        let template = Template::parse("{category}/").unwrap();
+        assert!(template
+            .render(&values(None))
+            .unwrap_err()
+            .contains("no file name"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Template::parse("{stem").unwrap_err(), "unclosed '{'");
+        assert_eq!(
+            Template::parse("{bpm}.wav").unwrap_err(),
+            "unknown placeholder '{bpm}'"
+        );
+        assert_eq!(
+            Template::parse("{index:x3}").unwrap_err(),
+            "invalid format ':x3'"
+        );
+        assert_eq!(
+            Template::parse("{stem:03}").unwrap_err(),
+            "'{stem}' takes no format"
+        );
+        assert!(Template::parse("a}b").is_err());
+    }
+}
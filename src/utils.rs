@@ -1,3 +1,7 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
 pub fn longest_common_prefix(strings: &[String]) -> String {
     if strings.is_empty() {
         return "".to_string();
@@ -8,7 +12,9 @@ pub fn longest_common_prefix(strings: &[String]) -> String {
 
     let mut prefix_len = 0;
     for (i, c) in lowercased_strings[0].chars().enumerate() {
-        let all_match = lowercased_strings.iter().all(|s| s.chars().nth(i) == Some(c));
+        let all_match = lowercased_strings
+            .iter()
+            .all(|s| s.chars().nth(i) == Some(c));
         if all_match {
             prefix_len += c.len_utf8();
         } else {
@@ -18,6 +24,137 @@ pub fn longest_common_prefix(strings: &[String]) -> String {
     first_string[..prefix_len].to_string()
 }
 
+/// Converts a byte offset into a 1-based (line, column) pair, counting columns in chars.
+pub fn line_col(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Translates a shell glob into an anchored regex. `*` and `?` don't cross `/`,
+/// `**` does, and `[...]` / `[!...]` are character classes. Like in a shell, a
+/// `]` right after `[` or `[!` is part of the class, and a `[` without a
+/// closing `]` is taken literally.
+pub fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                regex.push_str(".*");
+                i += 1;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let negated = chars.get(i + 1) == Some(&'!');
+                let start = i + 1 + negated as usize;
+                match chars
+                    .get(start + 1..)
+                    .and_then(|rest| rest.iter().position(|&c| c == ']'))
+                {
+                    Some(len) => {
+                        let end = start + 1 + len;
+                        regex.push('[');
+                        if negated {
+                            regex.push('^');
+                        }
+                        for j in start..end {
+                            // `&&`, `--` and `~~` are set operators in regex classes
+                            let doubled = j > start && chars[j - 1] == chars[j];
+                            if matches!(chars[j], '\\' | '[' | ']' | '^' | '&' | '~')
+                                || (chars[j] == '-' && doubled)
+                            {
+                                regex.push('\\');
+                            }
+                            regex.push(chars[j]);
+                        }
+                        regex.push(']');
+                        i = end;
+                    }
+                    None => regex.push_str("\\["),
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex
+}
+
+/// Explains why `name` cannot be used as a folder name on common filesystems.
+pub fn invalid_folder_name_reason(name: &str) -> Option<String> {
+    const RESERVED: [&str; 4] = ["con", "prn", "aux", "nul"];
+
+    if name.trim().is_empty() {
+        return Some("name is empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return Some(format!("'{}' refers to a special directory", name));
+    }
+    if let Some(c) = name.chars().find(|c| {
+        matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()
+    }) {
+        return Some(format!("contains forbidden character {:?}", c));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with a dot or space".to_string());
+    }
+    let stem = name.split('.').next().unwrap_or(name).to_lowercase();
+    let is_device = |prefix: &str| {
+        stem.strip_prefix(prefix)
+            .is_some_and(|n| n.len() == 1 && n.chars().all(|c| c.is_ascii_digit() && c != '0'))
+    };
+    if RESERVED.contains(&stem.as_str()) || is_device("com") || is_device("lpt") {
+        return Some(format!("'{}' is a reserved name on Windows", name));
+    }
+    None
+}
+
+/// Splits a name into lowercase tokens at separators, camelCase humps and
+/// letter/digit boundaries: `BD_808Long02` gives `bd`, `808`, `long`, `02`.
+/// A run of capitals keeps its last one for the next word, so `HTTPServer`
+/// gives `http`, `server`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let chars: Vec<char> = text.chars().collect();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if let Some(&prev) = i.checked_sub(1).and_then(|p| chars.get(p)) {
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            let boundary = prev.is_alphanumeric()
+                && (prev.is_numeric() != c.is_numeric()
+                    || (prev.is_lowercase() && c.is_uppercase())
+                    || (prev.is_uppercase() && c.is_uppercase() && next_lower));
+            if boundary && !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+        }
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// BLAKE3 hash of the contents of the file at `path`, in hex.
+pub fn file_checksum(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,11 +183,7 @@ mod tests {
 
     #[test]
     fn test_longest_common_prefix_no_common_prefix() {
-        let strings = vec![
-            "dog".to_string(),
-            "racecar".to_string(),
-            "car".to_string(),
-        ];
+        let strings = vec!["dog".to_string(), "racecar".to_string(), "car".to_string()];
         assert_eq!(longest_common_prefix(&strings), "");
     }
 
@@ -86,11 +219,79 @@ mod tests {
 
     #[test]
     fn test_longest_common_prefix_case_insensitive() {
-        let strings = vec![
-            "Apple".to_string(),
-            "apple".to_string(),
-            "App".to_string(),
-        ];
+        let strings = vec!["Apple".to_string(), "apple".to_string(), "App".to_string()];
         assert_eq!(longest_common_prefix(&strings), "App");
     }
+
+    #[test]
+    fn test_line_col() {
+        let content = "a = 1\nbb = \"ü\"x\n";
+        assert_eq!(line_col(content, 0), (1, 1));
+        assert_eq!(line_col(content, 6), (2, 1));
+        assert_eq!(line_col(content, content.find('x').unwrap()), (2, 9));
+    }
+
+    #[test]
+    fn test_invalid_folder_name_reason() {
+        assert_eq!(invalid_folder_name_reason("kicks"), None);
+        assert_eq!(invalid_folder_name_reason("808 kicks"), None);
+        assert_eq!(invalid_folder_name_reason("com10"), None);
+        assert!(invalid_folder_name_reason("").is_some());
+        assert!(invalid_folder_name_reason("..").is_some());
+        assert!(invalid_folder_name_reason("kicks?").is_some());
+        assert!(invalid_folder_name_reason("hats/open").is_some());
+        assert!(invalid_folder_name_reason("fx.").is_some());
+        assert!(invalid_folder_name_reason("Aux").is_some());
+        assert!(invalid_folder_name_reason("lpt1.wav").is_some());
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_eq!(glob_to_regex("bd_*.wav"), "^bd_[^/]*\\.wav$");
+        assert_eq!(glob_to_regex("kick??"), "^kick[^/][^/]$");
+        assert_eq!(glob_to_regex("drums/**"), "^drums/.*$");
+        assert_eq!(glob_to_regex("[!a-c]x"), "^[^a-c]x$");
+        assert_eq!(glob_to_regex("c++"), "^c\\+\\+$");
+        assert_eq!(glob_to_regex("kick[s"), "^kick\\[s$");
+    }
+
+    #[test]
+    fn test_glob_to_regex_bracket_edge_cases() {
+        assert_eq!(glob_to_regex("[]"), "^\\[\\]$");
+        assert_eq!(glob_to_regex("[!]"), "^\\[!\\]$");
+        assert_eq!(glob_to_regex("[]x]"), "^[\\]x]$");
+        assert_eq!(glob_to_regex("[!]x]"), "^[^\\]x]$");
+        assert_eq!(glob_to_regex("[a&&b]"), "^[a\\&\\&b]$");
+        assert_eq!(glob_to_regex("[a--b~~^]"), "^[a-\\-b\\~\\~\\^]$");
+        for glob in ["[]", "[!]", "[]x]", "[!]x]", "[a&&b]", "[~~^]", "[--x]"] {
+            assert!(regex::Regex::new(&glob_to_regex(glob)).is_ok(), "{}", glob);
+        }
+        let class = regex::Regex::new(&glob_to_regex("k[]!]ck")).unwrap();
+        assert!(class.is_match("k]ck") && class.is_match("k!ck") && !class.is_match("kick"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("BD_808Long02"), ["bd", "808", "long", "02"]);
+        assert_eq!(
+            tokenize("OpenHat-01 (dry).wav"),
+            ["open", "hat", "01", "dry", "wav"]
+        );
+        assert_eq!(tokenize("HTTPServer"), ["http", "server"]);
+        assert_eq!(tokenize("kik808b"), ["kik", "808", "b"]);
+        assert_eq!(tokenize("Drums/Hats/OH 1"), ["drums", "hats", "oh", "1"]);
+        assert!(tokenize(" -_ ").is_empty());
+    }
+
+    #[test]
+    fn test_file_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc.wav");
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            file_checksum(&path).unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert!(file_checksum(&dir.path().join("missing.wav")).is_err());
+    }
 }